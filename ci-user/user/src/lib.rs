@@ -119,6 +119,9 @@ pub struct TaskInfo {
     pub status: TaskStatus,
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
     pub time: usize,
+    pub peak_frames: usize,
+    pub resident_frames: usize,
+    pub page_faults: usize,
 }
 
 impl TaskInfo {
@@ -127,6 +130,9 @@ impl TaskInfo {
             status: TaskStatus::UnInit,
             syscall_times: [0; MAX_SYSCALL_NUM],
             time: 0,
+            peak_frames: 0,
+            resident_frames: 0,
+            page_faults: 0,
         }
     }
 }
@@ -6,14 +6,28 @@ pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
 pub const MEMORY_END: usize = 0x88000000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
+pub const HUGE_PAGE_SIZE: usize = PAGE_SIZE * 512;
 pub const MAX_SYSCALL_NUM: usize = 500;
+// 每个任务的虚拟地址空间大小的默认上限，usize::MAX 表示不限制
+pub const USER_AS_LIMIT: usize = usize::MAX;
+// 是否默认拒绝同时可写和可执行的 mmap/mprotect 请求（W^X），部分测例需要 RWX 的区域，因此默认关闭
+pub const WX_STRICT: bool = false;
+
+// 回收物理页帧时是否用 FRAME_POISON 填充整个页帧，便于发现释放后仍被访问的页帧，只在调试构建中开启
+pub const POISON_FREED_FRAMES: bool = cfg!(debug_assertions);
+pub const FRAME_POISON: u8 = 0xaa;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 
+// 内核地址空间中最多同时存在的内核栈数，每个内核栈占用一个槽位，任务退出后槽位被回收
+pub const MAX_KERNEL_STACKS: usize = 64;
+
 /// Return (bottom, top) of a kernel stack in kernel space.
-pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
-    let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
+///
+/// 相邻的两个内核栈之间留有一个保护页面
+pub fn kernel_stack_position(slot: usize) -> (usize, usize) {
+    let top = TRAMPOLINE - slot * (KERNEL_STACK_SIZE + PAGE_SIZE);
     let bottom = top - KERNEL_STACK_SIZE;
     (bottom, top)
 }
@@ -46,6 +46,12 @@ pub fn rust_main() -> ! {
     mm::init();
     println!("[kernel] back to world!");
     mm::remap_test();
+    // 调试构建中在启动时运行内核自带的测试，此时还没有开始运行任何应用
+    if cfg!(debug_assertions) {
+        mm::run_tests();
+        task::run_tests();
+        syscall::run_tests();
+    }
     trap::init();
     //trap::enable_interrupt();
     trap::enable_timer_interrupt();
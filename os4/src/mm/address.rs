@@ -6,6 +6,11 @@ use core::fmt::{self, Debug, Formatter};
 
 // 它们都是 usize 的一种简单包装。 将它们各自抽象出来而不是直接使用 usize，是为了在 Rust 编译器的帮助下进行多种方便且安全的 类型转换 (Type Convertion) 
 
+/// SV39 中虚拟地址的有效位数，更高的位必须是第 38 位的符号扩展
+const VA_WIDTH_SV39: usize = 39;
+/// SV39 中虚拟页号的有效位数
+const VPN_WIDTH_SV39: usize = VA_WIDTH_SV39 - PAGE_SIZE_BITS;
+
 /// physical address
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct PhysAddr(pub usize);
@@ -126,8 +131,13 @@ impl PhysAddr {
     }
 
     // 向上取整获得物理页号
+    // 地址为 0 时单独处理，避免 self.0 - 1 下溢
     pub fn ceil(&self) -> PhysPageNum {
-        PhysPageNum((self.0 - 1 + PAGE_SIZE) / PAGE_SIZE)
+        if self.0 == 0 {
+            PhysPageNum(0)
+        } else {
+            PhysPageNum((self.0 - 1) / PAGE_SIZE + 1)
+        }
     }
 
     // 获得页内偏移。其中 PAGE_SIZE 为 4096， PAGE_SIZE_BITS 为 12
@@ -139,6 +149,18 @@ impl PhysAddr {
     pub fn aligned(&self) -> bool {
         self.page_offset() == 0
     }
+
+    // 向下对齐到页边界，返回对齐后的物理地址
+    #[allow(unused)]
+    pub fn align_down(&self) -> PhysAddr {
+        self.floor().into()
+    }
+
+    // 向上对齐到页边界，返回对齐后的物理地址
+    #[allow(unused)]
+    pub fn align_up(&self) -> PhysAddr {
+        self.ceil().into()
+    }
 }
 
 /// 对虚拟地址的一些操作
@@ -149,8 +171,13 @@ impl VirtAddr {
     }
 
     // 向上取整获得虚拟页号
+    // 地址为 0 时单独处理，避免 self.0 - 1 下溢
     pub fn ceil(&self) -> VirtPageNum {
-        VirtPageNum((self.0 - 1 + PAGE_SIZE) / PAGE_SIZE)
+        if self.0 == 0 {
+            VirtPageNum(0)
+        } else {
+            VirtPageNum((self.0 - 1) / PAGE_SIZE + 1)
+        }
     }
 
     // 获得页内偏移。其中 PAGE_SIZE 为 4096， PAGE_SIZE_BITS 为 12
@@ -162,6 +189,35 @@ impl VirtAddr {
     pub fn aligned(&self) -> bool {
         self.page_offset() == 0
     }
+
+    /// 与 From<VirtAddr> for VirtPageNum 相同，但地址未按页对齐时返回 None 而不是 panic，
+    /// 用于校验来自用户程序的地址
+    #[allow(unused, clippy::wrong_self_convention)]
+    pub fn to_vpn_checked(&self) -> Option<VirtPageNum> {
+        if self.aligned() {
+            Some(self.floor())
+        } else {
+            None
+        }
+    }
+
+    // 向下对齐到页边界，返回对齐后的虚拟地址
+    #[allow(unused)]
+    pub fn align_down(&self) -> VirtAddr {
+        self.floor().into()
+    }
+
+    // 向上对齐到页边界，返回对齐后的虚拟地址
+    #[allow(unused)]
+    pub fn align_up(&self) -> VirtAddr {
+        self.ceil().into()
+    }
+
+    // 判断是否为合法的 SV39 虚拟地址，即 [63: 39] 位与第 38 位相同
+    pub fn is_canonical(&self) -> bool {
+        let high = self.0 >> (VA_WIDTH_SV39 - 1);
+        high == 0 || high == usize::MAX >> (VA_WIDTH_SV39 - 1)
+    }
 }
 
 
@@ -170,9 +226,15 @@ impl VirtAddr {
 impl PhysPageNum {
     /// 返回一个页表项定长数组的可变引用，可以用来修改多级页表中的一个节点
     pub fn get_pte_array(&self) -> &'static mut [PageTableEntry] {
-        let pa: PhysAddr = (*self).into();
         // 一个页 4K，能放 512 个页表项
-        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut PageTableEntry, 512) }
+        self.get_mut_array::<PageTableEntry>()
+    }
+
+    /// 泛型函数，将整个物理页帧视为类型为 T 的数组，元素个数为 PAGE_SIZE / size_of::<T>()
+    pub fn get_mut_array<T>(&self) -> &'static mut [T] {
+        let pa: PhysAddr = (*self).into();
+        let len = PAGE_SIZE / core::mem::size_of::<T>();
+        unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut T, len) }
     }
 
     /// 返回一个字节数组的可变引用，可以以字节为粒度对物理页帧上的数据进行访问
@@ -182,6 +244,19 @@ impl PhysPageNum {
         unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, 4096) }
     }
 
+    /// 返回当前物理页帧中 [start, start + len) 这一段字节的可变引用，要求这一段不超出当前页帧
+    pub fn get_bytes_range(&self, start: usize, len: usize) -> &'static mut [u8] {
+        assert!(
+            start <= PAGE_SIZE && len <= PAGE_SIZE - start,
+            "byte range [{:#x}, {:#x}+{:#x}) exceeds the page",
+            start,
+            start,
+            len
+        );
+        let pa: PhysAddr = (*self).into();
+        unsafe { core::slice::from_raw_parts_mut((pa.0 + start) as *mut u8, len) }
+    }
+
     /// 泛型函数，可以获取一个恰好放在一个物理页帧开头的类型为 T 的数据的可变引用
     pub fn get_mut<T>(&self) -> &'static mut T {
         let pa: PhysAddr = (*self).into();
@@ -202,6 +277,17 @@ impl VirtPageNum {
         }
         index
     }
+
+    /// 判断是否为合法的 SV39 虚拟地址的页号。indexes 只取低 27 位，不合法的页号会被当作另一个页号
+    pub fn is_canonical(&self) -> bool {
+        let high = self.0 >> (VPN_WIDTH_SV39 - 1);
+        high == 0 || high == usize::MAX >> (VA_WIDTH_SV39 - 1)
+    }
+
+    /// 判断是否位于 SV39 虚拟地址空间的低半部分（第 38 位为 0），应用的代码、数据、栈和 mmap 的区域都在这一半
+    pub fn in_user_half(&self) -> bool {
+        self.0 >> (VPN_WIDTH_SV39 - 1) == 0
+    }
 }
 
 
@@ -210,9 +296,36 @@ pub trait StepByOne {
     fn step(&mut self);
 }
 
+// 溢出时直接 panic，而不是在 release 模式下回绕到 0 导致遍历区间时死循环
 impl StepByOne for VirtPageNum {
     fn step(&mut self) {
-        self.0 += 1;
+        self.0 = self.0.checked_add(1).expect("virtual page number overflow");
+    }
+}
+
+impl StepByOne for PhysPageNum {
+    fn step(&mut self) {
+        self.0 = self
+            .0
+            .checked_add(1)
+            .expect("physical page number overflow");
+    }
+}
+
+/// 以页为单位的编号，可以取出其内部的 usize
+pub trait PageNumber {
+    fn as_usize(&self) -> usize;
+}
+
+impl PageNumber for VirtPageNum {
+    fn as_usize(&self) -> usize {
+        self.0
+    }
+}
+
+impl PageNumber for PhysPageNum {
+    fn as_usize(&self) -> usize {
+        self.0
     }
 }
 
@@ -237,12 +350,39 @@ where
         assert!(start <= end, "start {:?} > end {:?}!", start, end);
         Self { l: start, r: end }
     }
+    /// 与 new 相同，但在 start > end 时返回 None 而不是 panic，用于由用户传入参数构造的区间
+    pub fn try_new(start: T, end: T) -> Option<Self> {
+        if start <= end {
+            Some(Self { l: start, r: end })
+        } else {
+            None
+        }
+    }
     pub fn get_start(&self) -> T {
         self.l
     }
     pub fn get_end(&self) -> T {
         self.r
     }
+    /// 判断 item 是否位于左闭右开区间 [l, r) 中
+    pub fn contains(&self, item: T) -> bool {
+        self.l <= item && item < self.r
+    }
+}
+
+impl<T> SimpleRange<T>
+where
+    T: StepByOne + PageNumber + Copy + PartialEq + PartialOrd + Debug,
+{
+    /// 区间包含的页数，不需要遍历区间
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.r.as_usize() - self.l.as_usize()
+    }
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.l == self.r
+    }
 }
 
 /// 为 SimpleRange<T> 实现 into_iter trait
@@ -280,8 +420,9 @@ where
     T: StepByOne + Copy + PartialEq + PartialOrd + Debug,
 {
     type Item = T;
+    // 用 >= 而不是 == 判断结束，即使 current 越过了 end 也不会继续迭代
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current == self.end {
+        if self.current >= self.end {
             None
         } else {
             let t = self.current;
@@ -293,3 +434,98 @@ where
 
 /// a simple range structure for virtual page number
 pub type VPNRange = SimpleRange<VirtPageNum>;
+
+#[allow(unused)]
+/// 检查地址对齐相关的辅助函数
+pub fn address_test() {
+    let mid = VirtAddr::from(PAGE_SIZE + 0x123);
+    assert_eq!(mid.align_down(), VirtAddr::from(PAGE_SIZE));
+    assert_eq!(mid.align_up(), VirtAddr::from(2 * PAGE_SIZE));
+    let boundary = VirtAddr::from(2 * PAGE_SIZE);
+    assert_eq!(boundary.align_down(), boundary);
+    assert_eq!(boundary.align_up(), boundary);
+    let zero = VirtAddr::from(0);
+    assert_eq!(zero.align_down(), zero);
+    assert_eq!(zero.align_up(), zero);
+
+    let mid = PhysAddr::from(PAGE_SIZE - 1);
+    assert_eq!(mid.align_down(), PhysAddr::from(0));
+    assert_eq!(mid.align_up(), PhysAddr::from(PAGE_SIZE));
+    let boundary = PhysAddr::from(PAGE_SIZE);
+    assert_eq!(boundary.align_down(), boundary);
+    assert_eq!(boundary.align_up(), boundary);
+    let zero = PhysAddr::from(0);
+    assert_eq!(zero.align_down(), zero);
+    assert_eq!(zero.align_up(), zero);
+
+    // 直接检查 ceil 在 0 以及页面边界两侧的结果
+    for (addr, page) in [
+        (0, 0),
+        (1, 1),
+        (PAGE_SIZE - 1, 1),
+        (PAGE_SIZE, 1),
+        (PAGE_SIZE + 1, 2),
+    ] {
+        assert_eq!(VirtAddr::from(addr).ceil(), VirtPageNum(page));
+        assert_eq!(PhysAddr::from(addr).ceil(), PhysPageNum(page));
+    }
+
+    // start + len 超出页帧时 get_bytes_range 会 panic，这里只检查不越界的情况
+    let frame = super::frame_alloc().unwrap();
+    assert_eq!(frame.ppn.get_bytes_range(0x100, 0x80).len(), 0x80);
+    assert_eq!(frame.ppn.get_bytes_range(PAGE_SIZE, 0).len(), 0);
+    assert_eq!(frame.ppn.get_bytes_range(0, PAGE_SIZE).len(), PAGE_SIZE);
+    info!("address_test passed!");
+}
+
+pub fn simple_range_test() {
+    let empty = VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x10));
+    assert_eq!(empty.len(), 0);
+    assert!(empty.is_empty());
+    let single = VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x11));
+    assert_eq!(single.len(), 1);
+    assert!(!single.is_empty());
+    let multi = SimpleRange::new(PhysPageNum(0x80000), PhysPageNum(0x80010));
+    assert_eq!(multi.len(), 0x10);
+    assert_eq!(multi.len(), multi.into_iter().count());
+
+    assert!(single.contains(VirtPageNum(0x10)));
+    assert!(!single.contains(VirtPageNum(0x11)));
+    assert!(!single.contains(VirtPageNum(0xf)));
+    assert!(!empty.contains(VirtPageNum(0x10)));
+    info!("simple_range_test passed!");
+}
+
+pub fn get_mut_array_test() {
+    let frame = super::frame_alloc().unwrap();
+    assert_eq!(frame.ppn.get_mut_array::<PageTableEntry>().len(), 512);
+    assert_eq!(frame.ppn.get_mut_array::<u64>().len(), 512);
+    assert_eq!(frame.ppn.get_mut_array::<u32>().len(), 1024);
+    // 与 get_bytes_array 访问的是同一块内存
+    frame.ppn.get_mut_array::<u64>()[1] = u64::MAX;
+    assert_eq!(&frame.ppn.get_bytes_array()[8..16], &[0xff; 8]);
+    info!("get_mut_array_test passed!");
+}
+
+pub fn range_end_test() {
+    // SV39 中最大的虚拟页号，即跳板所在的页面
+    let max = VirtAddr::from(usize::MAX).floor();
+    let range = VPNRange::new(VirtPageNum(max.0 - 2), VirtPageNum(max.0 + 1));
+    assert_eq!(range.into_iter().count(), 3);
+    assert_eq!(range.into_iter().last(), Some(max));
+    // 结束于 usize::MAX 的区间同样能正常终止
+    let range = VPNRange::new(VirtPageNum(usize::MAX - 1), VirtPageNum(usize::MAX));
+    assert_eq!(range.into_iter().count(), 1);
+    assert_eq!(range.into_iter().next(), Some(VirtPageNum(usize::MAX - 1)));
+    info!("range_end_test passed!");
+}
+
+pub fn to_vpn_checked_test() {
+    assert_eq!(VirtAddr::from(0).to_vpn_checked(), Some(VirtPageNum(0)));
+    let aligned = VirtAddr::from(0x10 * PAGE_SIZE);
+    assert_eq!(aligned.to_vpn_checked(), Some(VirtPageNum(0x10)));
+    assert_eq!(aligned.to_vpn_checked(), Some(VirtPageNum::from(aligned)));
+    assert_eq!(VirtAddr::from(0x10 * PAGE_SIZE + 1).to_vpn_checked(), None);
+    assert_eq!(VirtAddr::from(PAGE_SIZE - 1).to_vpn_checked(), None);
+    info!("to_vpn_checked_test passed!");
+}
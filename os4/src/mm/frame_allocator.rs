@@ -2,10 +2,12 @@
 //! controls all the frames in the operating system.
 
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
+use crate::config::{FRAME_POISON, MEMORY_END, POISON_FREED_FRAMES};
 use crate::sync::UPSafeCell;
+use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 
 // 将物理页号封装成一个物理页帧
@@ -24,6 +26,12 @@ impl FrameTracker {
         }
         Self { ppn }
     }
+
+    /// 与 new 相同，但不清零页帧，调用者需要自行初始化其中的全部内容
+    #[allow(unused)]
+    pub fn new_uninit(ppn: PhysPageNum) -> Self {
+        Self { ppn }
+    }
 }
 
 impl Debug for FrameTracker {
@@ -40,27 +48,68 @@ impl Drop for FrameTracker {
 }
 
 // 物理页帧管理器
-trait FrameAllocator {
+/// 只管理物理页号，不访问页帧的内容，因此除了全局的 FRAME_ALLOCATOR 之外也可以在测试中单独构造实例
+pub trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
+/// 复用已回收物理页帧的顺序
+#[allow(unused)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RecyclePolicy {
+    // 优先复用最近回收的页帧，缓存局部性更好
+    Lifo,
+    // 优先复用最早回收的页帧，降低复用仍残留在 TLB 中的页帧的可能
+    Fifo,
+}
+
 // 栈式物理页帧管理
 /// an implementation for frame allocator
 pub struct StackFrameAllocator {
     // [current, end) 表示物理页号区间内此前从未被分配出去过
     current: usize,
     end: usize,
-    // vec(stack) 保存了被回收的物理页号，第三章已经实现了堆分配器
-    recycled: Vec<usize>,
+    // recycled 保存了被回收的物理页号，第三章已经实现了堆分配器
+    recycled: VecDeque<usize>,
+    // 从 recycled 中取出页帧的顺序
+    policy: RecyclePolicy,
+    // 保留的物理页号区间 [l, r)，例如设备 MMIO 和 DTB 所在的区域，永远不会被分配出去
+    reserved: Vec<(usize, usize)>,
 }
 
 impl StackFrameAllocator {
+    // 管理物理页号区间 [l, r)
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
         self.current = l.0;
         self.end = r.0;
     }
+
+    /// 设置复用已回收页帧的顺序
+    #[allow(unused)]
+    pub fn set_policy(&mut self, policy: RecyclePolicy) {
+        self.policy = policy;
+    }
+
+    /// 将物理页号区间 [l, r) 保留下来，之后的分配会跳过其中的物理页帧
+    #[allow(unused)]
+    pub fn reserve(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        if l.0 >= r.0 {
+            return;
+        }
+        self.reserved.push((l.0, r.0));
+        // 已经回收的页帧中落在保留区间内的不再参与分配
+        self.recycled.retain(|&ppn| ppn < l.0 || ppn >= r.0);
+    }
+
+    // 若 ppn 位于某个保留区间内，返回该区间的右端点
+    fn reserved_end(&self, ppn: usize) -> Option<usize> {
+        self.reserved
+            .iter()
+            .find(|&&(l, r)| l <= ppn && ppn < r)
+            .map(|&(_, r)| r)
+    }
 }
 
 // 物理页帧管理器需要实现 new、alloc、dealloc 方法
@@ -71,17 +120,27 @@ impl FrameAllocator for StackFrameAllocator {
         Self {
             current: 0,
             end: 0,
-            recycled: Vec::new(),
+            recycled: VecDeque::new(),
+            policy: RecyclePolicy::Lifo,
+            reserved: Vec::new(),
         }
     }
 
-    // 在分配 alloc 的时候，首先会检查栈 recycled 内有没有之前回收的物理页号，如果有的话直接弹出栈顶并返回
-    // 否则从 [current, end) 上进行分配
+    // 在分配 alloc 的时候，首先会检查 recycled 内有没有之前回收的物理页号，如果有的话按照 policy 取出一个并返回
+    // 否则从 [current, end) 上进行分配，并跳过保留区间
     // into 对应的 From trait 在 address.rs 中实现了
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
+        let recycled = match self.policy {
+            RecyclePolicy::Lifo => self.recycled.pop_back(),
+            RecyclePolicy::Fifo => self.recycled.pop_front(),
+        };
+        if let Some(ppn) = recycled {
+            return Some(ppn.into());
+        }
+        while let Some(r) = self.reserved_end(self.current) {
+            self.current = r.min(self.end);
+        }
+        if self.current >= self.end {
             None
         } else {
             self.current += 1;
@@ -89,7 +148,7 @@ impl FrameAllocator for StackFrameAllocator {
         }
     }
 
-    // 在回收 dealloc 的时候，我们需要检查回收页面的合法性，然后将其压入 recycled 栈中
+    // 在回收 dealloc 的时候，我们需要检查回收页面的合法性，然后将其放入 recycled 的末尾
     // 合法有两个条件：
     // 1. 该页面之前一定被分配出去过，因此它的物理页号一定小于 current 
     // 2. 该页面没有正处在回收状态，即它的物理页号不能在栈 recycled 中找到
@@ -99,8 +158,12 @@ impl FrameAllocator for StackFrameAllocator {
         if ppn >= self.current || self.recycled.iter().any(|v| *v == ppn) {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
+        // 在分配之后才被保留的页帧直接丢弃，不再回收
+        if self.reserved_end(ppn).is_some() {
+            return;
+        }
         // recycle
-        self.recycled.push(ppn);
+        self.recycled.push_back(ppn);
     }
 }
 
@@ -124,21 +187,134 @@ pub fn init_frame_allocator() {
     );
 }
 
+/// init_frame_allocator_with 保存的全局分配器状态，交给 restore_frame_allocator 恢复
+pub struct SavedFrameRange {
+    current: usize,
+    end: usize,
+    recycled: VecDeque<usize>,
+    // 被限制的物理页号区间 [l, r)
+    limited: (usize, usize),
+}
+
+/// 把全局分配器临时限制为只分配物理页号区间 [l, r) 中的页帧，用于确定地测试页帧耗尽，返回值交给 restore_frame_allocator 恢复
+///
+/// [l, r) 必须位于从未分配过的区间内；分配器本身没有被替换，限制期间仍然存活的旧页帧可以照常回收
+pub fn init_frame_allocator_with(l: PhysPageNum, r: PhysPageNum) -> SavedFrameRange {
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    assert!(
+        allocator.current <= l.0 && l.0 <= r.0 && r.0 <= allocator.end,
+        "frame range [{:#x}, {:#x}) has been allocated before",
+        l.0,
+        r.0
+    );
+    let saved = SavedFrameRange {
+        current: allocator.current,
+        end: allocator.end,
+        recycled: core::mem::take(&mut allocator.recycled),
+        limited: (l.0, r.0),
+    };
+    allocator.init(l, r);
+    saved
+}
+
+/// 撤销 init_frame_allocator_with 的限制，此时从 [l, r) 中分配出去的页帧必须已经全部回收
+pub fn restore_frame_allocator(saved: SavedFrameRange) {
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    let (l, r) = saved.limited;
+    let recycled = core::mem::replace(&mut allocator.recycled, saved.recycled);
+    let returned = recycled.iter().filter(|&&ppn| l <= ppn && ppn < r).count();
+    assert_eq!(
+        returned,
+        allocator.current - l,
+        "frames in [{:#x}, {:#x}) are still in use",
+        l,
+        r
+    );
+    // 限制期间回收的旧页帧仍然可以再次分配，[l, r) 中的页帧则回到从未分配过的区间
+    allocator
+        .recycled
+        .extend(recycled.into_iter().filter(|&ppn| ppn < l || ppn >= r));
+    allocator.current = saved.current;
+    allocator.end = saved.end;
+}
+
+// 剩余可以成功分配的次数，usize::MAX 表示不限制
+static ALLOC_FAIL_AFTER: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// 让 frame_alloc 在再成功分配 n 次之后开始返回 None，用于确定地测试页帧耗尽时的处理，传入 usize::MAX 恢复正常分配
+///
+/// 只在调试构建中生效，release 构建中 frame_alloc 不受影响
+#[allow(unused)]
+pub fn set_alloc_fail_after(n: usize) {
+    ALLOC_FAIL_AFTER.store(n, Ordering::Relaxed);
+}
+
+// 调试构建中消耗一次分配次数，次数已经耗尽时返回 false
+fn take_alloc_budget() -> bool {
+    if !cfg!(debug_assertions) {
+        return true;
+    }
+    ALLOC_FAIL_AFTER
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| match n {
+            0 => None,
+            usize::MAX => Some(usize::MAX),
+            n => Some(n - 1),
+        })
+        .is_ok()
+}
+
 // 分配物理页帧的接口
 /// allocate a frame
 pub fn frame_alloc() -> Option<FrameTracker> {
+    if !take_alloc_budget() {
+        return None;
+    }
     FRAME_ALLOCATOR
         .exclusive_access()
         .alloc()
         .map(FrameTracker::new)
 }
 
+// 回收页帧时获取分配器的次数，只在调试构建中统计，用于确认批量回收只获取一次
+static DEALLOC_LOCK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// 调试构建中记录一次回收页帧时对分配器的获取，release 构建中不做任何事
+fn count_dealloc_lock() {
+    if cfg!(debug_assertions) {
+        DEALLOC_LOCK_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// 开启 POISON_FREED_FRAMES 时用 FRAME_POISON 填充即将回收的页帧，既让释放后的访问读到明显异常的数据，也清除页帧中残留的内容
+fn poison_frame(ppn: PhysPageNum) {
+    if POISON_FREED_FRAMES {
+        ppn.get_bytes_array().fill(FRAME_POISON);
+    }
+}
+
 // 回收物理页帧的接口
 /// deallocate a frame
 fn frame_dealloc(ppn: PhysPageNum) {
+    count_dealloc_lock();
+    poison_frame(ppn);
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
 }
 
+/// 一次性回收多个物理页帧，只获取一次分配器，用于加速地址空间的回收
+///
+/// 调用者需要保证这些页帧对应的 FrameTracker 不会再被 drop
+pub fn frame_dealloc_bulk(ppns: &[PhysPageNum]) {
+    if ppns.is_empty() {
+        return;
+    }
+    count_dealloc_lock();
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    for &ppn in ppns {
+        poison_frame(ppn);
+        allocator.dealloc(ppn);
+    }
+}
+
 #[allow(unused)]
 /// a simple test for frame allocator
 pub fn frame_allocator_test() {
@@ -157,3 +333,128 @@ pub fn frame_allocator_test() {
     drop(v);
     info!("frame_allocator_test passed!");
 }
+
+pub fn frame_reserve_test() {
+    // 只操作物理页号而不访问页帧内容，因此可以使用一个独立的分配器
+    let mut allocator = StackFrameAllocator::new();
+    allocator.init(PhysPageNum(0x100), PhysPageNum(0x110));
+    let early = allocator.alloc().unwrap();
+    allocator.dealloc(early);
+    // 保留区间包含一个已经回收的页帧
+    allocator.reserve(PhysPageNum(0x100), PhysPageNum(0x101));
+    allocator.reserve(PhysPageNum(0x104), PhysPageNum(0x108));
+    let mut allocated = Vec::new();
+    while let Some(ppn) = allocator.alloc() {
+        assert!(ppn.0 != 0x100 && !(0x104..0x108).contains(&ppn.0));
+        allocated.push(ppn);
+    }
+    assert_eq!(allocated.len(), 0x10 - 5);
+    for ppn in allocated {
+        allocator.dealloc(ppn);
+    }
+    while let Some(ppn) = allocator.alloc() {
+        assert!(ppn.0 != 0x100 && !(0x104..0x108).contains(&ppn.0));
+    }
+    info!("frame_reserve_test passed!");
+}
+
+pub fn recycle_policy_test() {
+    for policy in [RecyclePolicy::Lifo, RecyclePolicy::Fifo] {
+        let mut allocator = StackFrameAllocator::new();
+        allocator.init(PhysPageNum(0x100), PhysPageNum(0x110));
+        allocator.set_policy(policy);
+        let frames: Vec<PhysPageNum> = (0..3).map(|_| allocator.alloc().unwrap()).collect();
+        for &ppn in frames.iter() {
+            allocator.dealloc(ppn);
+        }
+        let reused: Vec<PhysPageNum> = (0..3).map(|_| allocator.alloc().unwrap()).collect();
+        let expected: Vec<PhysPageNum> = match policy {
+            RecyclePolicy::Lifo => frames.iter().rev().copied().collect(),
+            RecyclePolicy::Fifo => frames,
+        };
+        assert_eq!(reused, expected);
+    }
+    info!("recycle_policy_test passed!");
+}
+
+pub fn frame_dealloc_bulk_test() {
+    let ppns: Vec<PhysPageNum> = (0..8)
+        .map(|_| {
+            let frame = frame_alloc().unwrap();
+            let ppn = frame.ppn;
+            core::mem::forget(frame);
+            ppn
+        })
+        .collect();
+    let before = DEALLOC_LOCK_COUNT.load(Ordering::Relaxed);
+    frame_dealloc_bulk(&ppns);
+    // 只有调试构建中才会统计获取分配器的次数
+    if cfg!(debug_assertions) {
+        assert_eq!(DEALLOC_LOCK_COUNT.load(Ordering::Relaxed), before + 1);
+    }
+    // 回收后的页帧可以再次分配
+    let frame = frame_alloc().unwrap();
+    assert!(ppns.contains(&frame.ppn));
+    info!("frame_dealloc_bulk_test passed!");
+}
+
+pub fn frame_exhaustion_test() {
+    // 把全局分配器限制在尚未分配过的 4 个页帧上，通过 frame_alloc 确定地触发页帧耗尽
+    let l = FRAME_ALLOCATOR.exclusive_access().current;
+    let saved = init_frame_allocator_with(PhysPageNum(l), PhysPageNum(l + 4));
+    let frames: Vec<FrameTracker> = (0..4).map(|_| frame_alloc().unwrap()).collect();
+    assert!(frames.iter().all(|frame| (l..l + 4).contains(&frame.ppn.0)));
+    assert!(frame_alloc().is_none());
+    // 回收页帧之后又可以分配
+    drop(frames);
+    let frame = frame_alloc().unwrap();
+    assert!((l..l + 4).contains(&frame.ppn.0));
+    drop(frame);
+    restore_frame_allocator(saved);
+    // 恢复之后从原来的区间继续分配
+    assert!(frame_alloc().is_some());
+    info!("frame_exhaustion_test passed!");
+}
+
+pub fn stack_frame_allocator_test() {
+    // 独立构造的分配器，不会影响全局的 FRAME_ALLOCATOR
+    let mut allocator = StackFrameAllocator::new();
+    assert!(allocator.alloc().is_none());
+    allocator.init(PhysPageNum(0x200), PhysPageNum(0x204));
+    // 从未分配过的页帧按物理页号递增的顺序分配
+    let frames: Vec<PhysPageNum> = (0..4).map(|_| allocator.alloc().unwrap()).collect();
+    assert_eq!(frames, (0x200..0x204).map(PhysPageNum).collect::<Vec<_>>());
+    assert!(allocator.alloc().is_none());
+    // 默认优先复用最近回收的页帧
+    allocator.dealloc(frames[1]);
+    allocator.dealloc(frames[3]);
+    assert_eq!(allocator.alloc(), Some(frames[3]));
+    assert_eq!(allocator.alloc(), Some(frames[1]));
+    assert!(allocator.alloc().is_none());
+    // 重新初始化后从新的区间开始分配
+    let mut allocator = StackFrameAllocator::new();
+    allocator.init(PhysPageNum(0x300), PhysPageNum(0x301));
+    assert_eq!(allocator.alloc(), Some(PhysPageNum(0x300)));
+    assert!(allocator.alloc().is_none());
+    info!("stack_frame_allocator_test passed!");
+}
+
+pub fn frame_poison_test() {
+    let frame = frame_alloc().unwrap();
+    let ppn = frame.ppn;
+    ppn.get_bytes_array()[..4].copy_from_slice(b"key!");
+    drop(frame);
+    // 默认优先复用最近回收的页帧，直接从分配器取出同一个页帧并跳过清零
+    let raw = FRAME_ALLOCATOR.exclusive_access().alloc().unwrap();
+    assert_eq!(raw, ppn);
+    let frame = FrameTracker::new_uninit(raw);
+    if POISON_FREED_FRAMES {
+        let bytes = frame.ppn.get_bytes_array();
+        assert!(bytes.iter().all(|&b| b == FRAME_POISON));
+    }
+    drop(frame);
+    // 通过 frame_alloc 分配时仍然会被清零
+    let frame = frame_alloc().unwrap();
+    assert!(frame.ppn.get_bytes_array().iter().all(|&b| b == 0));
+    info!("frame_poison_test passed!");
+}
@@ -1,16 +1,22 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
-use super::{frame_alloc, FrameTracker};
-use super::{PTEFlags, PageTable, PageTableEntry};
+use super::VPNRange;
+use super::{flush_tlb, PTEFlags, PageTable, PageTableEntry};
+use super::{frame_alloc, frame_dealloc_bulk, FrameTracker};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::config::{
+    HUGE_PAGE_SIZE, MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE,
+};
 use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use lazy_static::*;
 use riscv::register::satp;
 use spin::Mutex;
+use xmas_elf::header::{Class, Data, Machine};
 
 extern "C" {
     fn stext();
@@ -31,13 +37,78 @@ lazy_static! {
         Arc::new(Mutex::new(MemorySet::new_kernel()));
 }
 
+// 是否为每个页面的映射和取消映射输出一行日志，默认关闭
+static TRACE_MAPPINGS: AtomicBool = AtomicBool::new(false);
+// 开启日志以来输出的映射和取消映射事件数
+static MAP_EVENTS: AtomicUsize = AtomicUsize::new(0);
+static UNMAP_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// 开启或关闭逐页的映射日志，开启时清零事件计数
+///
+/// 追查页帧泄漏时可以开启它，对比 map 和 unmap 事件找出没有被取消映射的页面
+#[allow(unused)]
+pub fn set_trace_mappings(enabled: bool) {
+    if enabled {
+        MAP_EVENTS.store(0, Ordering::Relaxed);
+        UNMAP_EVENTS.store(0, Ordering::Relaxed);
+    }
+    TRACE_MAPPINGS.store(enabled, Ordering::Relaxed);
+}
+
+/// 开启日志以来输出的 (map, unmap) 事件数
+#[allow(unused)]
+pub fn mapping_events() -> (usize, usize) {
+    (
+        MAP_EVENTS.load(Ordering::Relaxed),
+        UNMAP_EVENTS.load(Ordering::Relaxed),
+    )
+}
+
+// 开启了映射日志时输出一个事件，vpn 此时必须仍然被映射
+fn trace_mapping(page_table: &PageTable, map: bool, vpn: VirtPageNum, perm: MapPermission) {
+    if !TRACE_MAPPINGS.load(Ordering::Relaxed) {
+        return;
+    }
+    let (event, counter) = if map {
+        ("map", &MAP_EVENTS)
+    } else {
+        ("unmap", &UNMAP_EVENTS)
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    let ppn = page_table.translate(vpn).map(|pte| pte.ppn());
+    info!("[mapping] {} {:?} -> {:?} {}", event, vpn, ppn, perm);
+}
+
+/// mmap/munmap 失败的原因
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MmapError {
+    // 起始地址没有按页对齐
+    Unaligned,
+    // port 中含有不支持的位，或者没有给出任何访问权限
+    InvalidPort,
+    // 地址范围溢出，或者覆盖了 Trap 上下文和跳板
+    InvalidRange,
+    // 与已经映射的页面或保护页面重叠
+    Overlap,
+    // 覆盖了跳板或 Trap 上下文所在的页面
+    Reserved,
+    // 物理页帧耗尽
+    OutOfMemory,
+    // 要取消映射的范围中有未映射的页面
+    NotMapped,
+    // 映射之后地址空间的总大小会超过任务的上限
+    LimitExceeded,
+    // 开启 W^X 时请求的区域同时可写和可执行
+    WriteExecute,
+}
+
 /// 地址空间，控制虚拟内存空间
 /// memory set structure, controls virtual-memory space
 pub struct MemorySet {
     // 挂着所有多级页表的节点所在的物理页帧
     page_table: PageTable,
-    // 每个 MapArea 下则挂着对应逻辑段中的数据所在的物理页帧
-    areas: Vec<MapArea>,
+    // 每个 MapArea 下则挂着对应逻辑段中的数据所在的物理页帧，按逻辑段的起始虚拟页号排序
+    areas: BTreeMap<VirtPageNum, MapArea>,
     // 这两部分 合在一起构成了一个地址空间所需的所有物理页帧
 }
 
@@ -47,7 +118,7 @@ impl MemorySet {
     pub fn new_bare() -> Self {
         Self {
             page_table: PageTable::new(),
-            areas: Vec::new(),
+            areas: BTreeMap::new(),
         }
     }
 
@@ -56,18 +127,53 @@ impl MemorySet {
         self.page_table.token()
     }
 
+    /// 判断 range 是否与地址空间中已有的逻辑段相交，或者与已有逻辑段的起始虚拟页号相同
+    fn overlaps(&self, range: VPNRange) -> bool {
+        if self.areas.contains_key(&range.get_start()) {
+            return true;
+        }
+        // 已有的逻辑段互不相交，只有起始于 range 之前的最后一个逻辑段和起始于 range 之内的逻辑段可能与之相交
+        self.areas
+            .range(..range.get_start())
+            .next_back()
+            .into_iter()
+            .chain(self.areas.range(range.get_start()..range.get_end()))
+            .any(|(_, area)| area.overlaps(range))
+    }
+
     /// push 方法可以在当前地址空间插入一个新的逻辑段 map_area
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
+        assert!(
+            !self.overlaps(map_area.vpn_range),
+            "map area [{:?}, {:?}) overlaps an existing area",
+            map_area.vpn_range.get_start(),
+            map_area.vpn_range.get_end()
+        );
         map_area.map(&mut self.page_table);
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
-        self.areas.push(map_area);
+        self.areas.insert(map_area.vpn_range.get_start(), map_area);
+    }
+
+    /// 与 push 相同，但在与已有逻辑段相交或物理页帧耗尽时返回 Err，此时地址空间保持不变
+    fn try_push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> Result<(), MmapError> {
+        if self.overlaps(map_area.vpn_range) {
+            return Err(MmapError::Overlap);
+        }
+        map_area
+            .try_map(&mut self.page_table)
+            .map_err(|_| MmapError::OutOfMemory)?;
+        if let Some(data) = data {
+            map_area.copy_data(&mut self.page_table, data);
+        }
+        self.areas.insert(map_area.vpn_range.get_start(), map_area);
+        Ok(())
     }
 
     /// 可以在当前地址空间插入一个 Framed 方式映射到物理内存的逻辑段
     /// 
-    /// 要保证同一地址空间内的任意两个逻辑段不能存在交集
+    /// 同一地址空间内的任意两个逻辑段不能存在交集，否则会 panic
     pub fn insert_framed_area(
         &mut self,
         start_va: VirtAddr,
@@ -83,11 +189,15 @@ impl MemorySet {
     
     /// 内核空间跳板
     /// Mention that trampoline is not collected by areas.
+    ///
+    /// G 位只设置在跳板上：跳板在每个地址空间中都映射到同一个物理页帧。内核的 .text/.rodata/.data 和物理内存的恒等映射
+    /// 并不存在于应用地址空间中，应用可以通过 mmap 在相同的虚拟地址上建立自己的映射，若带有 G 位，切换地址空间后
+    /// 快表中残留的内核表项会被应用命中，因此这些恒等映射不设置 G 位
     fn map_trampoline(&mut self) {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
-            PTEFlags::R | PTEFlags::X,
+            PTEFlags::R | PTEFlags::X | PTEFlags::G,
         );
     }
     /// Without kernel stacks.
@@ -154,39 +264,125 @@ impl MemorySet {
         );
 
         info!("mapping physical memory");
-        // 映射内核中的物理页帧
-        memory_set.push(
-            MapArea::new(
-                (ekernel as usize).into(),
-                MEMORY_END.into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
-        );
+        // 映射内核中的物理页帧，按 2MiB 对齐的部分使用大页映射以减少页表开销
+        let huge_start =
+            ((ekernel as usize + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1)).min(MEMORY_END);
+        let huge_end = (MEMORY_END & !(HUGE_PAGE_SIZE - 1)).max(huge_start);
+        for (start, end, map_type) in [
+            (ekernel as usize, huge_start, MapType::Identical),
+            (huge_start, huge_end, MapType::HugePage),
+            (huge_end, MEMORY_END, MapType::Identical),
+        ] {
+            // 跳过空的区间，避免与相邻逻辑段的起始虚拟页号相同
+            if start < end {
+                memory_set.push(
+                    MapArea::new(
+                        start.into(),
+                        end.into(),
+                        map_type,
+                        MapPermission::R | MapPermission::W,
+                    ),
+                    None,
+                );
+            }
+        }
         memory_set
     }
 
     /// 在创建应用地址空间的时候，我们需要对 get_app_data 得到的 ELF 格式数据进行解析，找到各个逻辑段所在位置和访问限制并插入进来，最终得到一个完整的应用地址空间
     /// 
     /// Include sections in elf and trampoline and TrapContext and user stack, also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    ///
+    /// ELF 不合法时返回 Err，此时不会映射任何逻辑段
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, usize), &'static str> {
         let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline();
-        // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
-        let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
+        let (user_stack_top, entry_point) = memory_set.load_elf(elf_data)?;
+        // 返回的时候，我们不仅返回应用地址空间 memory_set，也同时返回用户栈虚拟地址 user_stack_top 以及从解析 ELF 得到的该应用入口点地址，它们将被我们用来创建应用的任务控制块
+        Ok((memory_set, user_stack_top, entry_point))
+    }
+
+    /// 根据 serialize 得到的快照重新创建一个地址空间，跳板总是被重新映射
+    ///
+    /// 标记为 fresh 的逻辑段直接重新建立映射；其余逻辑段只为快照中记录的页面分配新的页帧并拷贝其内容，
+    /// 快照中没有记录的页面仍然属于逻辑段，之后由缺页异常分配
+    #[allow(unused)]
+    pub fn from_snapshot(snapshot: &[AreaSnapshot]) -> Self {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        for area_snapshot in snapshot {
+            let mut map_area = MapArea::new(
+                area_snapshot.start.into(),
+                area_snapshot.end.into(),
+                area_snapshot.map_type,
+                area_snapshot.map_permission,
+            );
+            map_area.guard = area_snapshot.guard;
+            map_area.global = area_snapshot.global;
+            map_area.no_access = area_snapshot.no_access;
+            if area_snapshot.fresh {
+                memory_set.push(map_area, None);
+                continue;
+            }
+            for (vpn, bytes) in area_snapshot.pages.iter() {
+                map_area.map_one(&mut memory_set.page_table, *vpn);
+                let ppn = memory_set.page_table.translate(*vpn).unwrap().ppn();
+                ppn.get_bytes_array().copy_from_slice(bytes);
+            }
+            memory_set.areas.insert(area_snapshot.start, map_area);
+        }
+        memory_set
+    }
+
+    /// 解析 ELF 并检查其合法性，包括魔数以及每个 LOAD 类型的 program header 在文件中的范围不超出 elf_data
+    pub fn parse_elf(elf_data: &[u8]) -> Result<xmas_elf::ElfFile<'_>, &'static str> {
+        let elf = xmas_elf::ElfFile::new(elf_data)?;
         // 取出 ELF 的魔数来判断它是不是一个合法的 ELF
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
-        let ph_count = elf_header.pt2.ph_count();
+        if elf.header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46] {
+            return Err("invalid elf!");
+        }
+        // 只接受小端序的 64 位 RISC-V ELF
+        if elf.header.pt1.class() != Class::SixtyFour {
+            return Err("elf is not 64-bit");
+        }
+        if elf.header.pt1.data() != Data::LittleEndian {
+            return Err("elf is not little-endian");
+        }
+        if elf.header.pt2.machine().as_machine() != Machine::RISC_V {
+            return Err("elf is not for RISC-V");
+        }
+        for i in 0..elf.header.pt2.ph_count() {
+            let ph = elf.program_header(i)?;
+            if ph.get_type()? != xmas_elf::program::Type::Load {
+                continue;
+            }
+            // 之后会以 [offset, offset + file_size) 从 elf_data 中切片拷贝数据，需要保证不越界
+            match ph.offset().checked_add(ph.file_size()) {
+                Some(end) if end <= elf_data.len() as u64 => {}
+                _ => return Err("program header out of bounds"),
+            }
+            if ph.file_size() > ph.mem_size() {
+                return Err("program header file_size exceeds mem_size");
+            }
+        }
+        Ok(elf)
+    }
+
+    /// 将 ELF 中的各个逻辑段、用户栈和 Trap 上下文映射到当前地址空间中，返回用户栈栈顶和入口点地址
+    ///
+    /// 调用前应保证这些区域尚未被映射，例如刚刚新建或者已经调用过 recycle_data_pages 的地址空间
+    pub fn load_elf(&mut self, elf_data: &[u8]) -> Result<(usize, usize), &'static str> {
+        // map program headers of elf, with U flag
+        // 在映射之前完成全部检查，保证出错时地址空间保持不变
+        let elf = Self::parse_elf(elf_data)?;
+        let ph_count = elf.header.pt2.ph_count();
         let mut max_end_vpn = VirtPageNum(0);
         // 然后遍历所有的 program header 并将合适的区域加入到应用地址空间中
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
+            let ph = elf.program_header(i)?;
             // 确认 program header 的类型是 LOAD
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+            if ph.get_type()? == xmas_elf::program::Type::Load {
                 // 通过 ph.virtual_addr() 和 ph.mem_size() 来计算这一区域在应用地址空间中的位置
                 let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
                 let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
@@ -205,7 +401,7 @@ impl MemorySet {
                 // 创建逻辑段 map_area 并 push 到应用地址空间，在 push 的时候我们需要完成数据拷贝
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
+                self.push(
                     map_area,
                     Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
                 );
@@ -220,7 +416,7 @@ impl MemorySet {
         let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
 
         // 在应用地址空间中映射次高页面来存放 Trap 上下文
-        memory_set.push(
+        self.push(
             MapArea::new(
                 user_stack_bottom.into(),
                 user_stack_top.into(),
@@ -230,7 +426,7 @@ impl MemorySet {
             None,
         );
         // map TrapContext
-        memory_set.push(
+        self.push(
             MapArea::new(
                 TRAP_CONTEXT.into(),
                 TRAMPOLINE.into(),
@@ -239,12 +435,7 @@ impl MemorySet {
             ),
             None,
         );
-        // 返回的时候，我们不仅返回应用地址空间 memory_set，也同时返回用户栈虚拟地址 user_stack_top 以及从解析 ELF 得到的该应用入口点地址，它们将被我们用来创建应用的任务控制块
-        (
-            memory_set,
-            user_stack_top,
-            elf.header.pt2.entry_point() as usize,
-        )
+        Ok((user_stack_top, elf.header.pt2.entry_point() as usize))
     }
 
     /// 我们将 token 写入当前 CPU 的 satp CSR ，从这一刻开始 SV39 分页模式就被启用了，而且 MMU 会使用内核地址空间的多级页表进行地址转换
@@ -262,17 +453,189 @@ impl MemorySet {
         self.page_table.translate(vpn)
     }
 
-    /// Lab2-os4 mmap 系统调用
-    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> isize {
-        let vpn_range = VPNRange::new(VirtAddr::from(start).floor(), VirtAddr::from(start + len).ceil());
+    /// 判断 vpn 是否位于恒等映射（包括大页）的逻辑段中
+    pub fn is_identical(&self, vpn: VirtPageNum) -> bool {
+        self.find_area(vpn).map_or(false, |area| {
+            matches!(area.map_type, MapType::Identical | MapType::HugePage)
+        })
+    }
 
-        for vpn in vpn_range {
+    /// translate 的快速路径：恒等映射的页面直接返回与 vpn 相同的物理页号，只需在 areas 中查找而不必遍历三级页表，
+    /// 其他页面仍然查页表
+    #[allow(unused)]
+    pub fn translate_kernel(&self, vpn: VirtPageNum) -> Option<PhysPageNum> {
+        if self.is_identical(vpn) {
+            Some(PhysPageNum(vpn.0))
+        } else {
+            self.page_table
+                .translate(vpn)
+                .filter(|pte| pte.is_valid())
+                .map(|pte| pte.ppn())
+        }
+    }
+
+    /// 取消所有逻辑段的映射并回收它们持有的物理页帧，页表本身（包括跳板的映射）保持不变，可用于 exec 时替换地址空间的内容
+    #[allow(unused)]
+    pub fn recycle_data_pages(&mut self) {
+        for (_, mut area) in core::mem::take(&mut self.areas) {
+            for vpn in area.vpn_range.into_iter().step_by(area.map_type.pages()) {
+                // 已经被 munmap 的页面不再需要取消映射
+                if self
+                    .page_table
+                    .find_pte(vpn)
+                    .map_or(false, |pte| pte.is_valid())
+                {
+                    area.unmap_one(&mut self.page_table, vpn);
+                }
+            }
+        }
+    }
+
+    /// 返回自上次扫描以来被访问过的 Framed 页面，并清除它们的 A 位，用于估计工作集
+    #[allow(unused)]
+    pub fn scan_accessed(&mut self) -> Vec<VirtPageNum> {
+        let mut accessed = Vec::new();
+        for area in self.areas.values() {
+            if area.map_type != MapType::Framed {
+                continue;
+            }
+            for &vpn in area.data_frames.keys() {
+                if self.page_table.clear_accessed(vpn) == Some(true) {
+                    accessed.push(vpn);
+                }
+            }
+        }
+        accessed
+    }
+
+    /// 当前地址空间中实际映射的物理页帧数，包括 Framed 逻辑段持有的页帧和恒等映射的页面，不含页表节点
+    pub fn resident_frames(&self) -> usize {
+        self.areas.values().map(|area| area.frame_count()).sum()
+    }
+
+    /// 所有逻辑段覆盖的虚拟地址空间的总字节数，包括尚未分配页帧的页面，不含保护页面
+    ///
+    /// 与 resident_frames 不同，可用于在 mmap 之前检查地址空间大小的上限
+    #[allow(unused)]
+    pub fn virtual_size(&self) -> usize {
+        self.areas
+            .values()
+            .map(|area| area.vpn_range.len() * PAGE_SIZE)
+            .sum()
+    }
+
+    /// 按起始地址排序返回所有逻辑段的 (起始地址, 终止地址, 访问权限, 映射方式)
+    pub fn describe(&self) -> Vec<(VirtAddr, VirtAddr, MapPermission, MapType)> {
+        self.areas
+            .values()
+            .map(|area| {
+                (
+                    area.vpn_range.get_start().into(),
+                    area.vpn_range.get_end().into(),
+                    area.map_permission,
+                    area.map_type,
+                )
+            })
+            .collect()
+    }
+
+    /// 按起始地址排序返回所有逻辑段的快照，用于检查点和恢复
+    ///
+    /// 跳板不属于任何逻辑段，不会出现在快照中，恢复时总是重新映射
+    #[allow(unused)]
+    pub fn serialize(&self) -> Vec<AreaSnapshot> {
+        self.areas.values().map(AreaSnapshot::from).collect()
+    }
+
+    /// 打印地址空间的布局，便于确认 mmap 等操作是否映射到了预期的位置
+    #[allow(unused)]
+    pub fn dump(&self) {
+        println!("[kernel] memory set {:#x}:", self.token());
+        for (start_va, end_va, permission, map_type) in self.describe() {
+            println!(
+                "  [{:?}, {:?}) {} {}",
+                start_va, end_va, permission, map_type
+            );
+        }
+    }
+
+    /// 将起始地址为 start 的逻辑段缩小到 new_end，被移出的页面会被取消映射
+    pub fn shrink_to(&mut self, start: VirtAddr, new_end: VirtAddr) -> bool {
+        if let Some(area) = self.areas.get_mut(&start.floor()) {
+            area.shrink_to(&mut self.page_table, new_end.ceil());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 将起始地址为 start 的逻辑段扩展到 new_end，新增的页面必须尚未被映射
+    pub fn append_to(&mut self, start: VirtAddr, new_end: VirtAddr) -> bool {
+        if new_end.0 > TRAP_CONTEXT {
+            return false;
+        }
+        let old_end = match self.areas.get(&start.floor()) {
+            Some(area) => area.vpn_range.get_end(),
+            None => return false,
+        };
+        let new_end = new_end.ceil();
+        if new_end < old_end {
+            return false;
+        }
+        for vpn in VPNRange::new(old_end, new_end) {
             if let Some(pte) = self.page_table.find_pte(vpn) {
                 if pte.is_valid() {
-                    return -1;
+                    return false;
                 }
             }
         }
+        self.areas
+            .get_mut(&start.floor())
+            .unwrap()
+            .append_to(&mut self.page_table, new_end);
+        true
+    }
+
+    /// Lab2-os4 mmap 系统调用，成功时返回映射区域的起始地址
+    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> Result<usize, MmapError> {
+        self.mmap_with(start, len, port, MmapFlags::empty())
+    }
+
+    /// 与 mmap 相同，flags 给出 port 之外的选项
+    pub fn mmap_with(
+        &mut self,
+        start: usize,
+        len: usize,
+        port: usize,
+        flags: MmapFlags,
+    ) -> Result<usize, MmapError> {
+        // start 要按页对齐
+        if !VirtAddr::from(start).aligned() {
+            return Err(MmapError::Unaligned);
+        }
+        // port 其余位必须为 0 且 0-2 位至少有一个为 1
+        if port & 0x7 == 0 || port & !0x7 != 0 {
+            return Err(MmapError::InvalidPort);
+        }
+        let guard = flags.contains(MmapFlags::GUARD);
+        // 作为建议地址时，与已有映射重叠则向上寻找第一段足够大（包括保护页面）的空闲区域
+        let hint = flags.contains(MmapFlags::HINT);
+        let mut start = start;
+        let vpn_range = loop {
+            match self.check_mmap_range(start, len, guard) {
+                Err(MmapError::Overlap) if hint => {
+                    // start 已经不可用，从下一页开始寻找，保证每轮都向上推进
+                    let next = VirtPageNum::from(VirtAddr::from(start).floor().0 + 1);
+                    let pages = VirtAddr::from(len).ceil().0 + guard as usize;
+                    let free = self
+                        .find_free_range(next, pages)
+                        .ok_or(MmapError::Overlap)?;
+                    start = VirtAddr::from(free).into();
+                }
+                result => break result?,
+            }
+        };
+        let end = start + len;
 
         let mut map_permission = MapPermission::U;
         if (port & 1) != 0 {
@@ -284,35 +647,521 @@ impl MemorySet {
         if (port & 4) != 0 {
             map_permission |= MapPermission::X;
         }
-        
-        println!("start_va: {:#x}, end_va: {:#x}, map_permission: {:#x}", start, start + len, map_permission);
+        debug!(
+            "mmap start_va: {:#x}, end_va: {:#x}, map_permission: {}",
+            start, end, map_permission
+        );
 
-        self.insert_framed_area(start.into(), (start + len).into(), map_permission);
-        0
+        // 物理页帧耗尽时只让本次 mmap 失败，而不是让内核 panic
+        let mut map_area = MapArea::new(start.into(), end.into(), MapType::Framed, map_permission);
+        map_area.guard = guard;
+        self.try_push(map_area, None)?;
+        if flags.contains(MmapFlags::ZERO_FILL) {
+            self.areas
+                .get_mut(&vpn_range.get_start())
+                .unwrap()
+                .zero_fill();
+        }
+        Ok(start)
+    }
+
+    /// 检查 [start, start + len) 能否被 mmap 映射，guard 表示是否还需要在其上保留一个保护页面
+    fn check_mmap_range(
+        &self,
+        start: usize,
+        len: usize,
+        guard: bool,
+    ) -> Result<VPNRange, MmapError> {
+        let end = start.checked_add(len).ok_or(MmapError::InvalidRange)?;
+        let vpn_range =
+            VPNRange::try_new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil())
+                .ok_or(MmapError::InvalidRange)?;
+
+        // 首尾地址都必须是合法的 SV39 地址，且位于同一半（符号位相同），不能跨越中间不可表示的区域
+        if len > 0 {
+            let last = end - 1;
+            if !VirtAddr::from(start).is_canonical()
+                || !VirtAddr::from(last).is_canonical()
+                || ((start ^ last) as isize) < 0
+            {
+                return Err(MmapError::InvalidRange);
+            }
+        }
+
+        // 跳板和 Trap 上下文所在的页面不一定出现在 areas 中，需要单独检查
+        if Self::reserved_vpns()
+            .iter()
+            .any(|&vpn| vpn_range.contains(vpn))
+        {
+            return Err(MmapError::Reserved);
+        }
+        // 映射范围不能超出 Trap 上下文之下的用户地址空间
+        if end > TRAP_CONTEXT {
+            return Err(MmapError::InvalidRange);
+        }
+
+        for vpn in vpn_range {
+            if let Some(pte) = self.page_table.find_pte(vpn) {
+                if pte.is_valid() {
+                    return Err(MmapError::Overlap);
+                }
+            }
+        }
+        // 页面没有映射也可能属于某个逻辑段，例如被 madvise 丢弃了页帧的页面
+        if self.overlaps(vpn_range) {
+            return Err(MmapError::Overlap);
+        }
+
+        // 不能占用其他逻辑段的保护页面
+        let collides_with_guard = self
+            .areas
+            .values()
+            .any(|area| area.guard && vpn_range.contains(area.vpn_range.get_end()));
+        if collides_with_guard {
+            return Err(MmapError::Overlap);
+        }
+
+        // 要求在映射区域之上保留一个保护页面时，该页面必须尚未被映射
+        if guard {
+            let guard_vpn = vpn_range.get_end();
+            if Self::reserved_vpns().contains(&guard_vpn) {
+                return Err(MmapError::Reserved);
+            }
+            if let Some(pte) = self.page_table.find_pte(guard_vpn) {
+                if pte.is_valid() {
+                    return Err(MmapError::Overlap);
+                }
+            }
+        }
+        Ok(vpn_range)
+    }
+
+    /// 从 hint 开始向上寻找第一段至少 pages 个页面、且不与任何逻辑段及其保护页面重叠的空闲区域，
+    /// 返回其起始虚拟页号；空闲区域必须位于 Trap 上下文之下，找不到时返回 None
+    pub fn find_free_range(&self, hint: VirtPageNum, pages: usize) -> Option<VirtPageNum> {
+        let limit = VirtAddr::from(TRAP_CONTEXT).floor().0;
+        let mut start = hint.0;
+        // 起始虚拟页号小于 hint 的最后一个逻辑段也可能覆盖 hint
+        let areas = self
+            .areas
+            .range(..hint)
+            .next_back()
+            .into_iter()
+            .chain(self.areas.range(hint..))
+            .map(|(_, area)| area);
+        for area in areas {
+            let area_start = area.vpn_range.get_start().0;
+            let area_end = area.vpn_range.get_end().0 + area.guard as usize;
+            // 不占据任何页面的逻辑段（如初始为空的堆）不影响查找
+            if area_start == area_end || area_end <= start {
+                continue;
+            }
+            if area_start >= start.checked_add(pages)? {
+                break;
+            }
+            start = area_end;
+        }
+        if start.checked_add(pages)? <= limit {
+            Some(VirtPageNum(start))
+        } else {
+            None
+        }
+    }
+
+    /// 将 va 所在的页面映射到 pa 所在的物理页帧，该页帧不经过 frame_alloc 分配，也不归地址空间所有
+    ///
+    /// va 和 pa 都要按页对齐；va 所在页面已被占用或者是跳板和 Trap 上下文，以及 pa 属于内核时返回 -1
+    #[allow(unused)]
+    pub fn map_physical(&mut self, va: VirtAddr, pa: PhysAddr, perm: MapPermission) -> isize {
+        if !va.aligned() || !pa.aligned() || !va.is_canonical() {
+            return -1;
+        }
+        // 内核镜像以及由页帧分配器管理的物理内存都属于内核，映射到用户空间会让用户篡改内核数据
+        if (stext as usize..MEMORY_END).contains(&pa.0) {
+            return -1;
+        }
+        let vpn = va.floor();
+        if Self::reserved_vpns().contains(&vpn) {
+            return -1;
+        }
+        if self
+            .page_table
+            .translate(vpn)
+            .map_or(false, |pte| pte.is_valid())
+        {
+            return -1;
+        }
+        let map_area = MapArea::new(
+            va,
+            VirtAddr::from(va.0 + PAGE_SIZE),
+            MapType::Direct(pa.floor()),
+            perm,
+        );
+        match self.try_push(map_area, None) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        }
+    }
+
+    /// 将共享段 segment 的全部页帧映射到以 va 开始的连续页面上，与其他映射了该共享段的地址空间访问的是同一组物理页帧
+    ///
+    /// va 要按页对齐且共享段不能为空；映射范围内已有页面被占用或者包含跳板和 Trap 上下文时返回 -1
+    #[allow(unused)]
+    pub fn attach_shared(
+        &mut self,
+        va: VirtAddr,
+        segment: &SharedSegment,
+        perm: MapPermission,
+    ) -> isize {
+        if !va.aligned() || segment.pages() == 0 {
+            return -1;
+        }
+        let end = match segment
+            .pages()
+            .checked_mul(PAGE_SIZE)
+            .and_then(|len| va.0.checked_add(len))
+        {
+            Some(end) => VirtAddr::from(end),
+            None => return -1,
+        };
+        let vpn_range = VPNRange::new(va.floor(), end.ceil());
+        if !va.is_canonical()
+            || !VirtAddr::from(end.0 - 1).is_canonical()
+            || Self::reserved_vpns()
+                .iter()
+                .any(|&vpn| vpn_range.contains(vpn))
+            || vpn_range.into_iter().any(|vpn| {
+                self.page_table
+                    .translate(vpn)
+                    .map_or(false, |pte| pte.is_valid())
+            })
+        {
+            return -1;
+        }
+        let mut map_area = MapArea::new(va, end, MapType::Shared(0), perm);
+        map_area.shared = Some(segment.clone());
+        match self.try_push(map_area, None) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        }
+    }
+
+    /// 跳板和 Trap 上下文所在的虚拟页号，用户不能通过 mmap 映射这两个页面
+    fn reserved_vpns() -> [VirtPageNum; 2] {
+        [
+            VirtAddr::from(TRAMPOLINE).floor(),
+            VirtAddr::from(TRAP_CONTEXT).floor(),
+        ]
+    }
+
+    /// 检查 vpn_range 中的每个页面是否都属于应用自己的 Framed 方式的逻辑段，供 mprotect 等修改已有映射的操作使用
+    ///
+    /// 跳板、Trap 上下文以及没有 U 位（且不是被 mprotect 设为不允许访问）的逻辑段只属于内核，返回 Err(MmapError::Reserved)，
+    /// 否则让应用修改其权限就可以篡改内核在 Trap 时信任的数据
+    fn check_user_range(&self, vpn_range: VPNRange) -> Result<(), MmapError> {
+        if Self::reserved_vpns()
+            .iter()
+            .any(|&vpn| vpn_range.contains(vpn))
+        {
+            return Err(MmapError::Reserved);
+        }
+        for vpn in vpn_range {
+            match self.find_area(vpn) {
+                Some(area) if area.map_type == MapType::Framed => {
+                    if !area.map_permission.contains(MapPermission::U) && !area.no_access {
+                        return Err(MmapError::Reserved);
+                    }
+                }
+                _ => return Err(MmapError::NotMapped),
+            }
+        }
+        Ok(())
     }
 
     /// Lab2-os4 munmap 系统调用
-    pub fn munmap(&mut self, start: usize, len: usize) -> isize {
-        let vpn_range = VPNRange::new(VirtAddr::from(start).floor(), VirtAddr::from(start + len).ceil());
+    ///
+    /// len 不是页面大小的整数倍时向上取整，即 [start, start + len) 所覆盖的最后一页也会被整页取消映射；
+    /// len 为 0 时不会取消任何映射，视为调用者的错误返回 Err(MmapError::InvalidRange)
+    pub fn munmap(&mut self, start: usize, len: usize) -> Result<(), MmapError> {
+        // start 要按页对齐
+        if !VirtAddr::from(start).aligned() {
+            return Err(MmapError::Unaligned);
+        }
+        if len == 0 {
+            return Err(MmapError::InvalidRange);
+        }
+        let end = start.checked_add(len).ok_or(MmapError::InvalidRange)?;
+        let vpn_range =
+            VPNRange::try_new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil())
+                .ok_or(MmapError::InvalidRange)?;
 
-        println!("{:?}", vpn_range);
-        
+        debug!("munmap {:?}", vpn_range);
+        // 被 madvise 丢弃了页帧的页面仍然属于某个逻辑段，同样可以取消映射
         for vpn in vpn_range {
             let pte = self.page_table.find_pte(vpn);
-            if pte.is_none() || !pte.unwrap().is_valid() {
-                return -1;
+            if (pte.is_none() || !pte.unwrap().is_valid()) && self.find_area(vpn).is_none() {
+                return Err(MmapError::NotMapped);
             }
         }
 
-        for vpn in vpn_range {
-            for area in &mut self.areas {
-                if vpn < area.vpn_range.get_end() && vpn >= area.vpn_range.get_start() {
-                    area.unmap_one(&mut self.page_table, vpn);
+        // 与 vpn_range 相交的逻辑段被拆成前缀、中间和后缀三部分，只取消映射并丢弃中间部分
+        for key in self.split_areas(vpn_range) {
+            let mut middle = self.areas.remove(&key).unwrap();
+            middle.unmap(&mut self.page_table);
+        }
+        Ok(())
+    }
+
+    // 把与 vpn_range 相交的逻辑段在 vpn_range 的两端拆开，返回完全落在 vpn_range 内的各逻辑段的起始虚拟页号
+    fn split_areas(&mut self, vpn_range: VPNRange) -> Vec<VirtPageNum> {
+        let (start_vpn, end_vpn) = (vpn_range.get_start(), vpn_range.get_end());
+        let intersects = |area: &MapArea| {
+            area.vpn_range.get_start().max(start_vpn) < area.vpn_range.get_end().min(end_vpn)
+        };
+        let keys: Vec<VirtPageNum> = self
+            .areas
+            .range(..start_vpn)
+            .next_back()
+            .into_iter()
+            .chain(self.areas.range(start_vpn..end_vpn))
+            .filter(|(_, area)| intersects(area))
+            .map(|(&key, _)| key)
+            .collect();
+        let mut middle_keys = Vec::new();
+        for key in keys {
+            let mut area = self.areas.remove(&key).unwrap();
+            if area.vpn_range.contains(end_vpn) {
+                let suffix = area.split(end_vpn);
+                self.areas.insert(end_vpn, suffix);
+            }
+            let (middle_key, middle) = if start_vpn > key {
+                let middle = area.split(start_vpn);
+                self.areas.insert(key, area);
+                (start_vpn, middle)
+            } else {
+                (key, area)
+            };
+            self.areas.insert(middle_key, middle);
+            middle_keys.push(middle_key);
+        }
+        middle_keys
+    }
+
+    /// 修改 [start, start + len) 中页面的访问权限，port 的 0-2 位依次表示 R/W/X，全为 0 表示不允许任何访问
+    ///
+    /// 不允许访问的页面仍然保留映射和页帧，只是去掉了 U 位，应用访问时触发缺页异常并被杀死，可以用作应用自己管理的保护页面。
+    /// 范围内有页面不属于任何 Framed 方式的逻辑段时返回 Err(MmapError::NotMapped)，包含跳板、Trap 上下文或其他只属于内核的页面时
+    /// 返回 Err(MmapError::Reserved)，此时不会修改任何页面
+    pub fn mprotect(&mut self, start: usize, len: usize, port: usize) -> Result<(), MmapError> {
+        if !VirtAddr::from(start).aligned() {
+            return Err(MmapError::Unaligned);
+        }
+        if port & !0x7 != 0 {
+            return Err(MmapError::InvalidPort);
+        }
+        let end = start.checked_add(len).ok_or(MmapError::InvalidRange)?;
+        let vpn_range =
+            VPNRange::try_new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil())
+                .ok_or(MmapError::InvalidRange)?;
+        self.check_user_range(vpn_range)?;
+        // 叶子页表项的 R/W/X 不能全为 0，不允许访问的页面只保留内核可读，与 Trap 上下文所在的页面一样不带 U 位
+        let map_permission = if port == 0 {
+            MapPermission::R
+        } else {
+            MapPermission::from_bits_truncate((port as u8) << 1) | MapPermission::U
+        };
+        for key in self.split_areas(vpn_range) {
+            let area = self.areas.get_mut(&key).unwrap();
+            area.map_permission = map_permission;
+            area.no_access = port == 0;
+            for (&vpn, frame) in area.data_frames.iter() {
+                self.page_table.remap(vpn, frame.ppn, map_permission.into());
+                if self.page_table.is_active() {
+                    flush_tlb(vpn);
                 }
             }
         }
+        Ok(())
+    }
+
+    /// 丢弃 [start, start + len) 中页面的物理页帧但保留其所在的逻辑段，之后再访问这些页面时由缺页异常重新分配清零的页帧
+    ///
+    /// 范围内有页面不属于任何 Framed 方式的逻辑段时返回 Err(MmapError::NotMapped)，包含跳板、Trap 上下文或其他只属于内核的页面时
+    /// 返回 Err(MmapError::Reserved)，此时不会丢弃任何页帧
+    pub fn madvise_dontneed(&mut self, start: usize, len: usize) -> Result<(), MmapError> {
+        if !VirtAddr::from(start).aligned() {
+            return Err(MmapError::Unaligned);
+        }
+        let end = start.checked_add(len).ok_or(MmapError::InvalidRange)?;
+        let vpn_range =
+            VPNRange::try_new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil())
+                .ok_or(MmapError::InvalidRange)?;
+        // 任务控制块中的 trap_cx_ppn 一直指向 Trap 上下文的页帧，丢弃它会让下一次 Trap 写入已经回收的页帧
+        self.check_user_range(vpn_range)?;
+        for vpn in vpn_range {
+            let (_, area) = self.areas.range_mut(..=vpn).next_back().unwrap();
+            if area.data_frames.contains_key(&vpn) {
+                area.unmap_one(&mut self.page_table, vpn);
+            }
+        }
+        Ok(())
+    }
+
+    /// 处理缺页异常：va 所在页面属于某个 Framed 方式的逻辑段但尚未分配页帧时，为其分配一个清零的页帧
+    ///
+    /// 返回 true 表示已经补上了映射，应用可以重新执行出错的指令；被 mprotect 设为不允许访问的页面不会补上映射
+    pub fn handle_page_fault(&mut self, va: VirtAddr) -> bool {
+        let vpn = va.floor();
+        match self.areas.range_mut(..=vpn).next_back() {
+            Some((_, area))
+                if area.vpn_range.contains(vpn)
+                    && area.map_type == MapType::Framed
+                    && area.map_permission.contains(MapPermission::U)
+                    && !area.data_frames.contains_key(&vpn) =>
+            {
+                area.try_map_one(&mut self.page_table, vpn).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    /// 检查 [start, start + len) 中的每个页面是否都已映射，且页表项至少带有 perm 中的全部权限
+    ///
+    /// 供系统调用在批量访问用户缓冲区之前一次性检查，范围溢出时返回 false
+    pub fn range_is_mapped(&self, start: usize, len: usize, perm: MapPermission) -> bool {
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        VPNRange::new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil())
+            .into_iter()
+            .all(|vpn| {
+                self.page_table.translate(vpn).map_or(false, |pte| {
+                    pte.is_valid() && MapPermission::from(pte.flags()).contains(perm)
+                })
+            })
+    }
+
+    /// 从另一个地址空间 other 中复制出 [start, start + len) 的数据，供调试器等任务查看其他任务的内存
+    ///
+    /// 只读取数据，不会修改任何一个地址空间；范围溢出或其中有未映射的页面时返回 None
+    #[allow(unused)]
+    pub fn copy_region_from(&self, other: &MemorySet, start: usize, len: usize) -> Option<Vec<u8>> {
+        let end = start.checked_add(len)?;
+        let mut bytes = Vec::with_capacity(len);
+        let mut current = start;
+        while current < end {
+            let va = VirtAddr::from(current);
+            let pte = other
+                .page_table
+                .translate(va.floor())
+                .filter(|pte| pte.is_valid())?;
+            let page_end = (current / PAGE_SIZE + 1) * PAGE_SIZE;
+            let n = page_end.min(end) - current;
+            bytes.extend_from_slice(pte.ppn().get_bytes_range(va.page_offset(), n));
+            current += n;
+        }
+        Some(bytes)
+    }
+
+    /// 检查每个逻辑段中已映射页面的页表项是否与逻辑段记录的物理页号和访问权限一致
+    #[allow(unused)]
+    pub fn verify_permissions(&self) -> bool {
+        self.areas.values().all(|area| {
+            area.iter_mappings().all(|(vpn, ppn)| {
+                self.page_table.translate(vpn).map_or(false, |pte| {
+                    pte.is_valid()
+                        && pte.ppn() == ppn
+                        && MapPermission::from(pte.flags()) == area.map_permission
+                })
+            })
+        })
+    }
+
+    /// 取消映射并移除起始虚拟页号恰好为 start_vpn 的逻辑段，返回该逻辑段是否存在
+    pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) -> bool {
+        match self.areas.remove(&start_vpn) {
+            Some(mut area) => {
+                area.unmap(&mut self.page_table);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 合并 areas 中相邻且映射方式和访问权限都相同的逻辑段
+    ///
+    /// 注意用户栈和其上的堆也可能被合并，合并后无法再以堆的起始地址找到堆所在的逻辑段
+    #[allow(unused)]
+    pub fn coalesce(&mut self) {
+        let mut areas = core::mem::take(&mut self.areas).into_values();
+        let mut current = match areas.next() {
+            Some(area) => area,
+            None => return,
+        };
+        for area in areas {
+            if current.can_merge(&area) {
+                current.merge(area);
+            } else {
+                let prev = core::mem::replace(&mut current, area);
+                self.areas.insert(prev.vpn_range.get_start(), prev);
+            }
+        }
+        self.areas.insert(current.vpn_range.get_start(), current);
+    }
+
+    /// 确保 [start, start + len) 中的每个页面都有物理页帧，为尚未分配页帧的页面提前分配
+    ///
+    /// 范围内有页面不属于任何逻辑段或者物理页帧耗尽时返回 -1
+    #[allow(unused)]
+    pub fn populate(&mut self, start: usize, len: usize) -> isize {
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return -1,
+        };
+        let vpn_range =
+            match VPNRange::try_new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil()) {
+                Some(vpn_range) => vpn_range,
+                None => return -1,
+            };
+        if vpn_range
+            .into_iter()
+            .any(|vpn| self.find_area(vpn).is_none())
+        {
+            return -1;
+        }
+        for vpn in vpn_range {
+            let (_, area) = self.areas.range_mut(..=vpn).next_back().unwrap();
+            // 只有 Framed 方式的逻辑段可能存在尚未分配页帧的页面
+            if area.map_type == MapType::Framed
+                && !area.data_frames.contains_key(&vpn)
+                && area.try_map_one(&mut self.page_table, vpn).is_err()
+            {
+                return -1;
+            }
+        }
         0
     }
+
+    /// 查找包含该虚拟页号的逻辑段，只需检查起始虚拟页号不超过 vpn 的最后一个逻辑段
+    #[allow(unused)]
+    pub fn find_area(&self, vpn: VirtPageNum) -> Option<&MapArea> {
+        self.areas
+            .range(..=vpn)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.vpn_range.contains(vpn))
+    }
+
+    /// 查找包含该虚拟页号的逻辑段的可变引用
+    #[allow(unused)]
+    pub fn find_area_mut(&mut self, vpn: VirtPageNum) -> Option<&mut MapArea> {
+        self.areas
+            .range_mut(..=vpn)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.vpn_range.contains(vpn))
+    }
 }
 
 /// 以逻辑段为单位描述一段连续地址的虚拟内存
@@ -325,9 +1174,117 @@ pub struct MapArea {
     map_type: MapType,
     // 表示控制该逻辑段的访问方式，它是页表项标志位 PTEFlags 的一个子集，仅保留 U/R/W/X 四个标志位，因为其他的标志位仅与硬件的地址转换机制细节相关，这样的设计能避免引入错误的标志位
     map_permission: MapPermission,
+    // 是否在逻辑段之上保留一个不可映射的保护页面
+    guard: bool,
+    // 是否在页表项中设置 G 位，用于在所有地址空间中都相同的内核映射
+    global: bool,
+    // 是否被 mprotect 设为不允许访问：此时没有 U 位，但仍然归应用所有，可以再次通过 mprotect 恢复访问
+    no_access: bool,
+    // Shared 方式映射的共享段，逻辑段持有一份引用使其中的页帧在逻辑段回收之前不会被释放
+    shared: Option<SharedSegment>,
 }
 
 
+/// 一个逻辑段的快照，记录了重新创建该逻辑段所需的全部信息
+#[derive(Clone, Debug)]
+pub struct AreaSnapshot {
+    pub start: VirtPageNum,
+    pub end: VirtPageNum,
+    pub map_permission: MapPermission,
+    pub map_type: MapType,
+    pub guard: bool,
+    pub global: bool,
+    pub no_access: bool,
+    /// 恢复时重新建立映射而不复制内容，例如恒等映射的内核段和 Direct 方式映射的设备内存
+    pub fresh: bool,
+    /// 每个已分配页帧的页面及其内容，没有页帧的页面（例如被 madvise 丢弃的页面）不会出现在其中
+    pub pages: Vec<(VirtPageNum, Vec<u8>)>,
+}
+
+impl From<&MapArea> for AreaSnapshot {
+    fn from(area: &MapArea) -> Self {
+        let fresh = matches!(
+            area.map_type,
+            MapType::Identical | MapType::HugePage | MapType::Direct(_)
+        );
+        let pages = if fresh {
+            Vec::new()
+        } else {
+            area.iter_mappings()
+                .map(|(vpn, ppn)| (vpn, ppn.get_bytes_array().to_vec()))
+                .collect()
+        };
+        Self {
+            start: area.vpn_range.get_start(),
+            end: area.vpn_range.get_end(),
+            map_permission: area.map_permission,
+            // 共享段在恢复之后无法继续与其他地址空间共享，按 Framed 方式保存其内容
+            map_type: match area.map_type {
+                MapType::Shared(_) => MapType::Framed,
+                map_type => map_type,
+            },
+            guard: area.guard,
+            global: area.global,
+            no_access: area.no_access,
+            fresh,
+            pages,
+        }
+    }
+}
+
+/// 可以同时映射到多个地址空间中的一组物理页帧，最后一个持有者被回收之后页帧才会被释放
+#[derive(Clone)]
+pub struct SharedSegment {
+    frames: Arc<Vec<FrameTracker>>,
+}
+
+impl SharedSegment {
+    /// 分配 pages 个清零的物理页帧组成共享段，物理页帧不足时返回 None
+    #[allow(unused)]
+    pub fn new(pages: usize) -> Option<Self> {
+        let frames = (0..pages)
+            .map(|_| frame_alloc())
+            .collect::<Option<Vec<FrameTracker>>>()?;
+        Some(Self {
+            frames: Arc::new(frames),
+        })
+    }
+
+    // 共享段包含的页面数
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+/// 显示逻辑段的范围、映射方式、访问权限和持有的物理页帧数，不列出具体的页帧
+impl fmt::Debug for MapArea {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MapArea")
+            .field("start", &self.vpn_range.get_start())
+            .field("end", &self.vpn_range.get_end())
+            .field("map_type", &self.map_type)
+            .field("map_permission", &self.map_permission)
+            .field("frames", &self.frame_count())
+            .finish()
+    }
+}
+
+/// 范围、映射方式和访问权限都相同的两个逻辑段相等，不比较实际映射到的物理页帧
+impl PartialEq for MapArea {
+    fn eq(&self, other: &Self) -> bool {
+        self.vpn_range.get_start() == other.vpn_range.get_start()
+            && self.vpn_range.get_end() == other.vpn_range.get_end()
+            && self.map_type == other.map_type
+            && self.map_permission == other.map_permission
+    }
+}
+
+impl Drop for MapArea {
+    fn drop(&mut self) {
+        self.release_frames();
+    }
+}
+
 impl MapArea {
     /// 新建一个逻辑段结构体，注意传入的起始/终止虚拟地址会分别被下取整/上取整为虚拟页号并传入 迭代器 vpn_range 中
     pub fn new(
@@ -343,102 +1300,346 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_permission,
+            guard: false,
+            global: false,
+            no_access: false,
+            shared: None,
         }
     }
 
+    /// 与 new 相同，但映射时会在页表项中设置 G 位，使快表在切换地址空间时可以保留这些表项
+    ///
+    /// G 位表示该映射存在于所有地址空间中，只能用于在每个地址空间中都以相同方式映射的逻辑段，
+    /// 内核的恒等映射并不存在于应用地址空间中，因此不能使用
+    #[allow(unused)]
+    pub fn new_global(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_type: MapType,
+        map_permission: MapPermission,
+    ) -> Self {
+        let mut map_area = Self::new(start_va, end_va, map_type, map_permission);
+        map_area.global = true;
+        map_area
+    }
+
     /// 实现一个虚拟页号映射到存放实际数据的物理页
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        // 这个 ppn 是存放实际数据的物理页，而不是中间级页表的物理页
-        let ppn: PhysPageNum;
-        match self.map_type {
+        self.try_map_one(page_table, vpn)
+            .expect("out of memory when mapping");
+    }
+
+    /// 与 map_one 相同，但在物理页帧耗尽时返回 Err 而不是 panic
+    pub fn try_map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), ()> {
+        let mut pte_flags = PTEFlags::from(self.map_permission);
+        if self.global {
+            pte_flags |= PTEFlags::G;
+        }
+        let result = match self.map_type {
             // 当以恒等映射 Identical 方式映射的时候，物理页号就等于虚拟页号
-            MapType::Identical => {
-                ppn = PhysPageNum(vpn.0);
-            }
+            MapType::Identical => page_table.try_map(vpn, PhysPageNum(vpn.0), pte_flags),
             // 当以 Framed 方式映射的时候，需要分配一个物理页帧让当前的虚拟页面可以映射过去，此时页表项中的物理页号自然就是这个被分配的物理页帧的物理页号。此时还需要将这个物理页帧挂在逻辑段的 data_frames 字段下。
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
+                // 这个 ppn 是存放实际数据的物理页，而不是中间级页表的物理页
+                let frame = frame_alloc().ok_or(())?;
+                // 在这里实际创建并填写了三级页表
+                page_table.try_map(vpn, frame.ppn, pte_flags)?;
                 self.data_frames.insert(vpn, frame);
+                Ok(())
+            }
+            MapType::Direct(_) => page_table.try_map(vpn, self.direct_ppn(vpn), pte_flags),
+            MapType::Shared(_) => page_table.try_map(vpn, self.shared_ppn(vpn), pte_flags),
+            // 大页同样是恒等映射，但直接在第二级页表中填写叶子页表项
+            MapType::HugePage => {
+                page_table.map_huge(vpn, PhysPageNum(vpn.0), pte_flags);
+                Ok(())
+            }
+        };
+        // 修改的是正在使用的页表时，刷新快表中对应页面的表项
+        if result.is_ok() {
+            if page_table.is_active() {
+                flush_tlb(vpn);
             }
+            trace_mapping(page_table, true, vpn, self.map_permission);
         }
-        let pte_flags = PTEFlags::from_bits(self.map_permission.bits).unwrap();
-        // 在这里实际创建并填写了三级页表
-        page_table.map(vpn, ppn, pte_flags);
+        result
     }
 
     /// 删除虚拟页号到物理页的映射关系
     #[allow(unused)]
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        trace_mapping(page_table, false, vpn, self.map_permission);
         #[allow(clippy::single_match)]
         match self.map_type {
             MapType::Framed => {
                 self.data_frames.remove(&vpn);
             }
+            MapType::HugePage => {
+                page_table.unmap_huge(vpn);
+                if page_table.is_active() {
+                    flush_tlb(vpn);
+                }
+                return;
+            }
             _ => {}
         }
         page_table.unmap(vpn);
+        if page_table.is_active() {
+            flush_tlb(vpn);
+        }
+    }
+
+    // Direct 方式下 vpn 对应的物理页号，与逻辑段起始页面的偏移保持一致
+    fn direct_ppn(&self, vpn: VirtPageNum) -> PhysPageNum {
+        match self.map_type {
+            MapType::Direct(ppn) => PhysPageNum(ppn.0 + (vpn.0 - self.vpn_range.get_start().0)),
+            _ => unreachable!(),
+        }
+    }
+
+    // Shared 方式下 vpn 对应的共享段中的物理页号
+    fn shared_ppn(&self, vpn: VirtPageNum) -> PhysPageNum {
+        match (self.map_type, &self.shared) {
+            (MapType::Shared(start), Some(segment)) => {
+                segment.frames[start + (vpn.0 - self.vpn_range.get_start().0)].ppn
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// 该逻辑段映射的物理页帧数，Framed 方式为实际持有的页帧数，恒等映射和 Direct 方式则为逻辑段覆盖的页面数
+    pub fn frame_count(&self) -> usize {
+        match self.map_type {
+            MapType::Framed => self.data_frames.len(),
+            MapType::Identical | MapType::HugePage | MapType::Direct(_) | MapType::Shared(_) => {
+                self.vpn_range.get_end().0 - self.vpn_range.get_start().0
+            }
+        }
+    }
+
+    /// 判断该逻辑段是否与 range 相交
+    ///
+    /// 不论其中的页面是否持有页帧：被 madvise 丢弃了页帧的页面仍然属于该逻辑段
+    pub fn overlaps(&self, range: VPNRange) -> bool {
+        let start = self.vpn_range.get_start().max(range.get_start());
+        let end = self.vpn_range.get_end().min(range.get_end());
+        start < end
+    }
+
+    /// 在虚拟页号 at 处将逻辑段一分为二，self 保留 [start, at)，返回 [at, end) 并将其中的物理页帧一并转移
+    ///
+    /// at 必须严格位于逻辑段内部；保护页面位于原逻辑段之上，因此归属于返回的后半段
+    pub fn split(&mut self, at: VirtPageNum) -> MapArea {
+        assert!(
+            self.vpn_range.get_start() < at && at < self.vpn_range.get_end(),
+            "split point {:?} not inside map area",
+            at
+        );
+        // Direct 和 Shared 方式的后半段从 at 对应的物理页帧开始
+        let offset = at.0 - self.vpn_range.get_start().0;
+        let map_type = match self.map_type {
+            MapType::Direct(_) => MapType::Direct(self.direct_ppn(at)),
+            MapType::Shared(start) => MapType::Shared(start + offset),
+            map_type => map_type,
+        };
+        let tail = MapArea {
+            vpn_range: VPNRange::new(at, self.vpn_range.get_end()),
+            data_frames: self.data_frames.split_off(&at),
+            map_type,
+            map_permission: self.map_permission,
+            guard: self.guard,
+            global: self.global,
+            no_access: self.no_access,
+            shared: self.shared.clone(),
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), at);
+        self.guard = false;
+        tail
+    }
+
+    /// 判断 other 是否紧接在当前逻辑段之后，且映射方式和访问权限都相同，从而可以合并
+    ///
+    /// 空逻辑段（例如尚未扩展的堆）以及 Direct 和 Shared 方式的逻辑段不参与合并
+    pub fn can_merge(&self, other: &MapArea) -> bool {
+        !matches!(self.map_type, MapType::Direct(_) | MapType::Shared(_))
+            && self.vpn_range.get_end() == other.vpn_range.get_start()
+            && self.vpn_range.get_start() < self.vpn_range.get_end()
+            && other.vpn_range.get_start() < other.vpn_range.get_end()
+            && self.map_type == other.map_type
+            && self.map_permission == other.map_permission
+            && self.global == other.global
+            && self.no_access == other.no_access
+    }
+
+    /// 将紧接在当前逻辑段之后的 other 合并进来，并接管其物理页帧
+    pub fn merge(&mut self, mut other: MapArea) {
+        assert!(self.can_merge(&other), "map areas cannot be merged");
+        self.data_frames.append(&mut other.data_frames);
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), other.vpn_range.get_end());
+        self.guard = other.guard;
+    }
+
+    /// 按虚拟页号升序遍历该逻辑段中已映射的页面，返回 (虚拟页号, 物理页号)
+    ///
+    /// Framed 方式从 data_frames 中取出实际的页帧，已经被 munmap 的页面会被跳过；恒等映射则物理页号等于虚拟页号
+    #[allow(unused)]
+    pub fn iter_mappings(&self) -> impl Iterator<Item = (VirtPageNum, PhysPageNum)> + '_ {
+        self.vpn_range
+            .into_iter()
+            .filter_map(move |vpn| match self.map_type {
+                MapType::Framed => self.data_frames.get(&vpn).map(|frame| (vpn, frame.ppn)),
+                MapType::Identical | MapType::HugePage => Some((vpn, PhysPageNum(vpn.0))),
+                MapType::Direct(_) => Some((vpn, self.direct_ppn(vpn))),
+                MapType::Shared(_) => Some((vpn, self.shared_ppn(vpn))),
+            })
+    }
+
+    /// 将该逻辑段持有的所有物理页帧清零
+    pub fn zero_fill(&mut self) {
+        for frame in self.data_frames.values() {
+            frame.ppn.get_bytes_array().fill(0);
+        }
+    }
+
+    /// 缩小逻辑段，取消映射 [new_end, end) 中的页面
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(new_end, self.vpn_range.get_end()) {
+            self.unmap_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+
+    /// 扩展逻辑段，映射 [end, new_end) 中的页面
+    pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        for vpn in VPNRange::new(self.vpn_range.get_end(), new_end) {
+            self.map_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
     }
 
     /// 将当前逻辑段到物理内存的映射加入传入的该逻辑段所属的地址空间的多级页表
     /// 
     /// 实现步骤是：对于每一个虚拟页号，都分配一个存放实际数据的物理页
     pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
+        for vpn in self.vpn_range.into_iter().step_by(self.map_type.pages()) {
             self.map_one(page_table, vpn);
         }
     }
 
+    /// 与 map 相同，但在物理页帧耗尽时撤销已经建立的映射并返回 Err
+    pub fn try_map(&mut self, page_table: &mut PageTable) -> Result<(), ()> {
+        for vpn in self.vpn_range.into_iter().step_by(self.map_type.pages()) {
+            if self.try_map_one(page_table, vpn).is_err() {
+                for mapped in VPNRange::new(self.vpn_range.get_start(), vpn)
+                    .into_iter()
+                    .step_by(self.map_type.pages())
+                {
+                    self.unmap_one(page_table, mapped);
+                }
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
     /// 将当前逻辑段到物理内存的映射从传入的该逻辑段所属的地址空间的多级页表中删除
     #[allow(unused)]
     pub fn unmap(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
+        if self.map_type == MapType::Framed {
+            // 先删除全部映射，再一次性回收所有页帧
+            for &vpn in self.data_frames.keys() {
+                trace_mapping(page_table, false, vpn, self.map_permission);
+                page_table.unmap(vpn);
+                if page_table.is_active() {
+                    flush_tlb(vpn);
+                }
+            }
+            self.release_frames();
+            return;
+        }
+        for vpn in self.vpn_range.into_iter().step_by(self.map_type.pages()) {
             self.unmap_one(page_table, vpn);
         }
     }
 
+    /// 将 data_frames 中的页帧一次性还给页帧分配器，避免逐个 drop FrameTracker 时反复获取分配器
+    fn release_frames(&mut self) {
+        let ppns: Vec<PhysPageNum> = core::mem::take(&mut self.data_frames)
+            .into_values()
+            .map(|frame| {
+                let ppn = frame.ppn;
+                core::mem::forget(frame);
+                ppn
+            })
+            .collect();
+        frame_dealloc_bulk(&ppns);
+    }
+
     /// 将切片 data 中的数据拷贝到当前逻辑段实际被内核放置在的各物理页帧上，从而 在地址空间中通过该逻辑段就能访问这些数据
     /// 
     /// 调用它的时候需要满足：切片 data 中的数据大小不超过当前逻辑段的 总大小，且切片中的数据会被对齐到逻辑段的开头，然后逐页拷贝到实际的物理页帧。
     /// 
     /// data: start-aligned but maybe with shorter length
     /// 
-    /// assume that all frames were cleared before
+    /// data 之后直到逻辑段末尾的部分（例如位于段尾的 .bss）会被显式清零，不依赖页帧分配时已经清零
     pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+        self.copy_data_checked(page_table, data)
+            .expect("data exceeds map area");
+    }
+
+    /// 与 copy_data 相同，但在 data 长度超过逻辑段大小时返回 Err 而不是写越界
+    pub fn copy_data_checked(&mut self, page_table: &mut PageTable, data: &[u8]) -> Result<(), ()> {
         // 保证要以 framed 方式映射
         assert_eq!(self.map_type, MapType::Framed);
-        let mut start: usize = 0;
-        // 获得起始逻辑页号
-        let mut current_vpn = self.vpn_range.get_start();
-        let len = data.len();
-
-
-        loop {
-            // 按照一页大小进行拷贝
-            let src = &data[start..len.min(start + PAGE_SIZE)];
+        let area_size = (self.vpn_range.get_end().0 - self.vpn_range.get_start().0) * PAGE_SIZE;
+        if data.len() > area_size {
+            return Err(());
+        }
+        // 没有数据（例如 file_size 为 0 的 .bss 段）时只需清零，逻辑段为空时也不会访问任何页面
+        if data.is_empty() {
+            self.zero_fill();
+            return Ok(());
+        }
+        // 按照一页大小进行拷贝，逐个遍历逻辑段内的页面，长度恰好是页面大小的整数倍时也不会多访问一页
+        let mut chunks = data.chunks(PAGE_SIZE);
+        for vpn in self.vpn_range {
             // 从页表中查询该虚拟页号对应的物理页号，然后写入 data
-            let dst = &mut page_table
-                .translate(current_vpn)
-                .unwrap()
-                .ppn()
-                .get_bytes_array()[..src.len()];
-            dst.copy_from_slice(src);
-            start += PAGE_SIZE;
-            if start >= len {
-                break;
-            }
-            // 虚拟页号加一
-            current_vpn.step();
+            let ppn = page_table.translate(vpn).unwrap().ppn();
+            let src = chunks.next().unwrap_or(&[]);
+            ppn.get_bytes_range(0, src.len()).copy_from_slice(src);
+            // 页面中 data 之后的部分以及之后没有数据的页面清零
+            ppn.get_bytes_range(src.len(), PAGE_SIZE - src.len())
+                .fill(0);
         }
+        Ok(())
     }
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 /// MapType 描述该逻辑段内的所有虚拟页面映射到物理页帧的同一种方式，它是一个枚举类型，在内核当前的实现中支持两种方式
 /// 其中 Identical 表示恒等映射，用于在启用多级页表之后仍能够访问一个特定的物理地址指向的物理内存；而 Framed 则表示对于每个虚拟页面都需要映射到一个新分配的物理页帧
+/// 
+/// HugePage 同样是恒等映射，但以 2MiB 大页为单位，要求逻辑段的起止地址按 2MiB 对齐
+///
+/// Direct 将逻辑段映射到从给定物理页号开始的连续物理页帧上，这些页帧只被记录而不归逻辑段所有，回收逻辑段时不会释放
+///
+/// Shared 将逻辑段映射到共享段中从给定下标开始的页帧上，页帧由所有映射了该共享段的逻辑段共同持有
 pub enum MapType {
     Identical,
     Framed,
+    HugePage,
+    Direct(PhysPageNum),
+    Shared(usize),
+}
+
+impl MapType {
+    /// 每次映射所覆盖的 4KiB 页面数
+    pub fn pages(&self) -> usize {
+        match self {
+            MapType::HugePage => HUGE_PAGE_SIZE / PAGE_SIZE,
+            _ => 1,
+        }
+    }
 }
 
 bitflags! {
@@ -451,6 +1652,83 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// mmap 在 port 之外的选项
+    pub struct MmapFlags: u8 {
+        /// 显式清零映射的页面，不依赖页帧分配时是否已经清零
+        const ZERO_FILL = 1 << 0;
+        /// 在映射区域之上保留一个不可映射的保护页面
+        const GUARD = 1 << 1;
+        /// start 只是建议的地址，与已有映射重叠时向上寻找空闲区域
+        const HINT = 1 << 2;
+    }
+}
+
+/// 依次以 r/w/x/u 表示四个权限位，缺少的权限用 - 占位，例如 R | W | U 显示为 rw-u
+///
+/// Debug 由 bitflags 生成，显示为 R | W | U 的形式
+impl Display for MapPermission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (Self::R, 'r'),
+            (Self::W, 'w'),
+            (Self::X, 'x'),
+            (Self::U, 'u'),
+        ];
+        for (flag, c) in flags {
+            write!(f, "{}", if self.contains(flag) { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+/// 显示映射方式的名称，Direct 和 Shared 携带的参数只在 Debug 中显示
+impl Display for MapType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MapType::Identical => "Identical",
+            MapType::Framed => "Framed",
+            MapType::HugePage => "HugePage",
+            MapType::Direct(_) => "Direct",
+            MapType::Shared(_) => "Shared",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 逐位转换 R/W/X/U，不依赖两者的位布局相同
+impl From<MapPermission> for PTEFlags {
+    fn from(permission: MapPermission) -> Self {
+        let mut flags = PTEFlags::empty();
+        for (perm, flag) in PERMISSION_FLAGS {
+            if permission.contains(perm) {
+                flags |= flag;
+            }
+        }
+        flags
+    }
+}
+
+/// 只保留 R/W/X/U，其余标志位（V/G/A/D 等）被丢弃
+impl From<PTEFlags> for MapPermission {
+    fn from(flags: PTEFlags) -> Self {
+        let mut permission = MapPermission::empty();
+        for (perm, flag) in PERMISSION_FLAGS {
+            if flags.contains(flag) {
+                permission |= perm;
+            }
+        }
+        permission
+    }
+}
+
+const PERMISSION_FLAGS: [(MapPermission, PTEFlags); 4] = [
+    (MapPermission::R, PTEFlags::R),
+    (MapPermission::W, PTEFlags::W),
+    (MapPermission::X, PTEFlags::X),
+    (MapPermission::U, PTEFlags::U),
+];
+
 #[allow(unused)]
 pub fn remap_test() {
     let mut kernel_space = KERNEL_SPACE.lock();
@@ -472,5 +1750,1096 @@ pub fn remap_test() {
         .translate(mid_data.floor())
         .unwrap()
         .executable());
+    // 物理内存末尾附近由大页映射，中间某个地址应仍能正确地恒等翻译
+    let mid_huge: VirtAddr = (MEMORY_END - HUGE_PAGE_SIZE / 2 + PAGE_SIZE).into();
+    assert_eq!(
+        kernel_space.page_table.translate(mid_huge.floor()).unwrap().ppn().0,
+        mid_huge.floor().0
+    );
+    assert!(kernel_space.verify_permissions());
     info!("remap_test passed!");
 }
+
+pub fn mmap_bounds_test() {
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(
+        memory_set.mmap(TRAMPOLINE - PAGE_SIZE, PAGE_SIZE, 0b011),
+        Err(MmapError::Reserved)
+    );
+    assert_eq!(
+        memory_set.mmap(TRAP_CONTEXT - PAGE_SIZE, PAGE_SIZE + 1, 0b011),
+        Err(MmapError::Reserved)
+    );
+    assert_eq!(
+        memory_set.mmap(TRAP_CONTEXT - PAGE_SIZE, PAGE_SIZE, 0b011),
+        Ok(TRAP_CONTEXT - PAGE_SIZE)
+    );
+    info!("mmap_bounds_test passed!");
+}
+
+pub fn iter_mappings_test() {
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        VirtAddr::from(0x10000),
+        VirtAddr::from(0x13000),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    let area = memory_set.areas.values().next_back().unwrap();
+    let mappings: Vec<_> = area.iter_mappings().collect();
+    assert_eq!(mappings.len(), 3);
+    for (i, (vpn, ppn)) in mappings.iter().enumerate() {
+        assert_eq!(vpn.0, 0x10 + i);
+        assert_eq!(*ppn, area.data_frames[vpn].ppn);
+        assert_eq!(memory_set.translate(*vpn).unwrap().ppn(), *ppn);
+    }
+    info!("iter_mappings_test passed!");
+}
+
+pub fn overlap_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let permission = MapPermission::R | MapPermission::W | MapPermission::U;
+    let first = MapArea::new(0x10000.into(), 0x13000.into(), MapType::Framed, permission);
+    assert!(memory_set.try_push(first, None).is_ok());
+    let second = MapArea::new(0x12000.into(), 0x14000.into(), MapType::Framed, permission);
+    assert!(memory_set.try_push(second, None).is_err());
+    assert_eq!(memory_set.areas.len(), 1);
+    let adjacent = MapArea::new(0x13000.into(), 0x14000.into(), MapType::Framed, permission);
+    assert!(memory_set.try_push(adjacent, None).is_ok());
+    info!("overlap_test passed!");
+}
+
+/// 在第一个应用的 ELF 中找到第一个 LOAD 类型 program header 在文件中的偏移
+fn first_load_ph(elf: &[u8]) -> usize {
+    let read = |pos: usize, len: usize| {
+        elf[pos..pos + len]
+            .iter()
+            .rev()
+            .fold(0usize, |acc, &byte| acc << 8 | byte as usize)
+    };
+    let (ph_off, ph_size, ph_count) = (read(32, 8), read(54, 2), read(56, 2));
+    (0..ph_count)
+        .map(|i| ph_off + i * ph_size)
+        .find(|&ph| read(ph, 4) == 1)
+        .unwrap()
+}
+
+pub fn elf_bounds_test() {
+    let mut elf = crate::loader::get_app_data(0).to_vec();
+    let len = elf.len() as u64;
+    let ph = first_load_ph(&elf);
+    // p_offset 超出文件末尾
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&elf[ph + 8..ph + 16]);
+    elf[ph + 8..ph + 16].copy_from_slice(&(len + 1).to_le_bytes());
+    assert!(MemorySet::from_elf(&elf).is_err());
+    elf[ph + 8..ph + 16].copy_from_slice(&offset);
+    // p_offset + p_filesz 超出文件末尾
+    let file_size = len - u64::from_le_bytes(offset) + 1;
+    elf[ph + 32..ph + 40].copy_from_slice(&file_size.to_le_bytes());
+    assert!(MemorySet::from_elf(&elf).is_err());
+    info!("elf_bounds_test passed!");
+}
+
+pub fn elf_arch_test() {
+    let app = crate::loader::get_app_data(0);
+    assert!(MemorySet::parse_elf(app).is_ok());
+    // EI_CLASS 为 1 表示 32 位 ELF
+    let mut elf = app.to_vec();
+    elf[4] = 1;
+    assert!(MemorySet::parse_elf(&elf).is_err());
+    // e_machine 为 0x3e 表示 x86-64
+    let mut elf = app.to_vec();
+    elf[18..20].copy_from_slice(&0x3eu16.to_le_bytes());
+    assert!(MemorySet::parse_elf(&elf).is_err());
+    info!("elf_arch_test passed!");
+}
+
+pub fn copy_data_test() {
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        VirtAddr::from(0x10000),
+        VirtAddr::from(0x12000),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    let area = memory_set.areas.values_mut().next_back().unwrap();
+    // 先弄脏页帧，模拟没有清零的情况
+    for frame in area.data_frames.values() {
+        frame.ppn.get_bytes_array().fill(0xff);
+    }
+    // file_size 为 100 字节，mem_size 为两页
+    let data = [0x5au8; 100];
+    area.copy_data(&mut memory_set.page_table, &data);
+    let first = area.data_frames[&VirtPageNum(0x10)].ppn.get_bytes_array();
+    assert!(first[..100].iter().all(|&byte| byte == 0x5a));
+    assert!(first[100..].iter().all(|&byte| byte == 0));
+    let second = area.data_frames[&VirtPageNum(0x11)].ppn.get_bytes_array();
+    assert!(second.iter().all(|&byte| byte == 0));
+    info!("copy_data_test passed!");
+}
+
+pub fn copy_data_checked_test() {
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        VirtAddr::from(0x10000),
+        VirtAddr::from(0x12000),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    let area = memory_set.areas.values_mut().next_back().unwrap();
+    let page_table = &mut memory_set.page_table;
+    let data = [0x5au8; 2 * PAGE_SIZE + 1];
+    let exact = &data[..2 * PAGE_SIZE];
+    assert!(area.copy_data_checked(page_table, exact).is_ok());
+    assert!(area.copy_data_checked(page_table, &data).is_err());
+    assert!(area.copy_data_checked(page_table, &data[..10]).is_ok());
+    info!("copy_data_checked_test passed!");
+}
+
+pub fn munmap_partial_test() {
+    let start = 0x10000;
+    let base = start / PAGE_SIZE;
+    // 以相对于 start 的页号描述各逻辑段的范围和持有页帧的页面
+    let ranges = |memory_set: &MemorySet| -> Vec<(usize, usize)> {
+        memory_set
+            .areas
+            .values()
+            .map(|area| {
+                let (l, r) = (area.vpn_range.get_start(), area.vpn_range.get_end());
+                (l.0 - base, r.0 - base)
+            })
+            .collect()
+    };
+    let frames = |memory_set: &MemorySet, i: usize| -> Vec<usize> {
+        let area = memory_set.areas.values().nth(i).unwrap();
+        area.data_frames.keys().map(|vpn| vpn.0 - base).collect()
+    };
+    // 取消映射 5 页逻辑段的第一页
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(memory_set.mmap(start, 5 * PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE), Ok(()));
+    assert_eq!(ranges(&memory_set), [(1, 5)]);
+    assert_eq!(frames(&memory_set, 0), [1, 2, 3, 4]);
+    let pte = memory_set.translate(VirtPageNum(base));
+    assert!(pte.map_or(true, |pte| !pte.is_valid()));
+    // 取消映射最后一页
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(memory_set.mmap(start, 5 * PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(memory_set.munmap(start + 4 * PAGE_SIZE, PAGE_SIZE), Ok(()));
+    assert_eq!(ranges(&memory_set), [(0, 4)]);
+    assert_eq!(frames(&memory_set, 0), [0, 1, 2, 3]);
+    // 取消映射中间一页，留下前后两个逻辑段
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(memory_set.mmap(start, 5 * PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(memory_set.munmap(start + 2 * PAGE_SIZE, PAGE_SIZE), Ok(()));
+    assert_eq!(ranges(&memory_set), [(0, 2), (3, 5)]);
+    assert_eq!(frames(&memory_set, 0), [0, 1]);
+    assert_eq!(frames(&memory_set, 1), [3, 4]);
+    // 范围中有未映射的页面时失败
+    assert_eq!(
+        memory_set.munmap(start + PAGE_SIZE, 2 * PAGE_SIZE),
+        Err(MmapError::NotMapped)
+    );
+    info!("munmap_partial_test passed!");
+}
+
+pub fn split_test() {
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        VirtAddr::from(0x10000),
+        VirtAddr::from(0x14000),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    let area = memory_set.areas.values_mut().next_back().unwrap();
+    let ppns: Vec<_> = area.data_frames.values().map(|frame| frame.ppn).collect();
+    let tail = area.split(VirtPageNum(0x12));
+    assert_eq!(area.vpn_range.get_start(), VirtPageNum(0x10));
+    assert_eq!(area.vpn_range.get_end(), VirtPageNum(0x12));
+    assert_eq!(tail.vpn_range.get_start(), VirtPageNum(0x12));
+    assert_eq!(tail.vpn_range.get_end(), VirtPageNum(0x14));
+    assert_eq!(tail.map_type, area.map_type);
+    assert_eq!(tail.map_permission, area.map_permission);
+    // 物理页帧随页面一起转移，不会重新分配
+    let head_ppns: Vec<_> = area.data_frames.values().map(|frame| frame.ppn).collect();
+    let tail_ppns: Vec<_> = tail.data_frames.values().map(|frame| frame.ppn).collect();
+    assert_eq!(head_ppns, ppns[..2]);
+    assert_eq!(tail_ppns, ppns[2..]);
+    memory_set.areas.insert(VirtPageNum(0x12), tail);
+    info!("split_test passed!");
+}
+
+pub fn coalesce_test() {
+    let mut memory_set = MemorySet::new_bare();
+    // 逆序映射三个相邻且权限相同的区域
+    assert_eq!(memory_set.mmap(0x12000, PAGE_SIZE, 0b011), Ok(0x12000));
+    assert_eq!(memory_set.mmap(0x11000, PAGE_SIZE, 0b011), Ok(0x11000));
+    assert_eq!(memory_set.mmap(0x10000, PAGE_SIZE, 0b011), Ok(0x10000));
+    // 权限不同的相邻区域不会被合并
+    assert_eq!(memory_set.mmap(0x13000, PAGE_SIZE, 0b001), Ok(0x13000));
+    memory_set.coalesce();
+    assert_eq!(memory_set.areas.len(), 2);
+    let area = memory_set.areas.values().next().unwrap();
+    assert_eq!(area.vpn_range.get_start(), VirtPageNum(0x10));
+    assert_eq!(area.vpn_range.get_end(), VirtPageNum(0x13));
+    assert_eq!(area.data_frames.len(), 3);
+    info!("coalesce_test passed!");
+}
+
+pub fn find_area_test() {
+    let mut memory_set = MemorySet::new_bare();
+    // 1000 个互不相邻的单页逻辑段，每次查找只访问 BTreeMap 中 O(log n) 个节点
+    let base = 0x10000 / PAGE_SIZE;
+    for i in 0..1000 {
+        let start = VirtAddr::from(VirtPageNum(base + 2 * i));
+        let end = VirtAddr::from(VirtPageNum(base + 2 * i + 1));
+        memory_set.push(
+            MapArea::new(start, end, MapType::Identical, MapPermission::R),
+            None,
+        );
+    }
+    let start_time = crate::timer::get_time_us();
+    for i in 0..1000 {
+        let (mapped, gap) = (VirtPageNum(base + 2 * i), VirtPageNum(base + 2 * i + 1));
+        let area = memory_set.find_area(mapped).unwrap();
+        assert_eq!(area.vpn_range.get_start(), mapped);
+        assert!(memory_set.find_area(gap).is_none());
+    }
+    info!(
+        "find_area_test passed! 2000 lookups in {} us",
+        crate::timer::get_time_us() - start_time
+    );
+}
+
+pub fn scan_accessed_test() {
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(memory_set.mmap(0x10000, 4 * PAGE_SIZE, 0b011), Ok(0x10000));
+    // 模拟硬件在访问页面时置位 A 位
+    let flags = PTEFlags::R | PTEFlags::W | PTEFlags::U | PTEFlags::A;
+    let touched = [VirtPageNum(0x11), VirtPageNum(0x13)];
+    for &vpn in touched.iter() {
+        let ppn = memory_set.translate(vpn).unwrap().ppn();
+        memory_set.page_table.remap(vpn, ppn, flags);
+    }
+    assert_eq!(memory_set.scan_accessed(), touched);
+    for &vpn in touched.iter() {
+        assert!(!memory_set.translate(vpn).unwrap().accessed());
+    }
+    assert!(memory_set.scan_accessed().is_empty());
+    info!("scan_accessed_test passed!");
+}
+
+pub fn permission_conversion_test() {
+    for bits in 0..16u8 {
+        let permission = MapPermission::from_bits(bits << 1).unwrap();
+        let flags = PTEFlags::from(permission);
+        assert_eq!(MapPermission::from(flags), permission);
+        // 硬件相关的标志位在转换时被丢弃
+        let hardware_flags = flags | PTEFlags::V | PTEFlags::A;
+        assert_eq!(MapPermission::from(hardware_flags), permission);
+    }
+    info!("permission_conversion_test passed!");
+}
+
+pub fn mmap_error_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    assert_eq!(
+        memory_set.mmap(start + 1, PAGE_SIZE, 0b011),
+        Err(MmapError::Unaligned)
+    );
+    assert_eq!(
+        memory_set.mmap(start, PAGE_SIZE, 0),
+        Err(MmapError::InvalidPort)
+    );
+    assert_eq!(
+        memory_set.mmap(start, PAGE_SIZE, 0b1000011),
+        Err(MmapError::InvalidPort)
+    );
+    assert_eq!(
+        memory_set.mmap(start, PAGE_SIZE, 0b1011),
+        Err(MmapError::InvalidPort)
+    );
+    assert_eq!(
+        memory_set.mmap(usize::MAX - PAGE_SIZE + 1, PAGE_SIZE, 0b011),
+        Err(MmapError::InvalidRange)
+    );
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(
+        memory_set.mmap(start, PAGE_SIZE, 0b011),
+        Err(MmapError::Overlap)
+    );
+    assert_eq!(
+        memory_set.munmap(start + 1, PAGE_SIZE),
+        Err(MmapError::Unaligned)
+    );
+    assert_eq!(
+        memory_set.munmap(start + PAGE_SIZE, PAGE_SIZE),
+        Err(MmapError::NotMapped)
+    );
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE), Ok(()));
+    info!("mmap_error_test passed!");
+}
+
+pub fn populate_test() {
+    let mut memory_set = MemorySet::new_bare();
+    // 直接插入一个尚未映射任何页面的逻辑段，模拟按需分配的区域
+    let start = 0x10000;
+    let end = start + 4 * PAGE_SIZE;
+    let map_area = MapArea::new(
+        start.into(),
+        end.into(),
+        MapType::Framed,
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    memory_set
+        .areas
+        .insert(VirtAddr::from(start).floor(), map_area);
+    assert_eq!(memory_set.resident_frames(), 0);
+    assert_eq!(memory_set.populate(start, 5 * PAGE_SIZE), -1);
+    assert_eq!(memory_set.resident_frames(), 0);
+    assert_eq!(memory_set.populate(start + PAGE_SIZE, PAGE_SIZE), 0);
+    assert_eq!(memory_set.resident_frames(), 1);
+    assert_eq!(memory_set.populate(start, end - start), 0);
+    assert_eq!(memory_set.resident_frames(), 4);
+    for vpn in VPNRange::new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil()) {
+        assert!(memory_set.translate(vpn).unwrap().is_valid());
+    }
+    info!("populate_test passed!");
+}
+
+pub fn mmap_reserved_test() {
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(
+        memory_set.mmap(TRAP_CONTEXT, PAGE_SIZE, 0b011),
+        Err(MmapError::Reserved)
+    );
+    // TRAMPOLINE + PAGE_SIZE 会溢出，只映射跳板页面中的一个字节
+    assert_eq!(
+        memory_set.mmap(TRAMPOLINE, 1, 0b011),
+        Err(MmapError::Reserved)
+    );
+    // 保护页面同样不能落在 Trap 上下文上
+    assert_eq!(
+        memory_set.mmap_with(TRAP_CONTEXT - PAGE_SIZE, PAGE_SIZE, 0b011, MmapFlags::GUARD),
+        Err(MmapError::Reserved)
+    );
+    assert_eq!(memory_set.resident_frames(), 0);
+    info!("mmap_reserved_test passed!");
+}
+
+pub fn mmap_canonical_test() {
+    let mut memory_set = MemorySet::new_bare();
+    // 第 39 位为 1 而更高位为 0，不是合法的 SV39 地址
+    assert_eq!(
+        memory_set.mmap(1 << 39, PAGE_SIZE, 0b011),
+        Err(MmapError::InvalidRange)
+    );
+    // 跨越低半部分的末尾
+    assert_eq!(
+        memory_set.mmap((1 << 38) - PAGE_SIZE, 2 * PAGE_SIZE, 0b011),
+        Err(MmapError::InvalidRange)
+    );
+    assert_eq!(
+        memory_set.mmap((1 << 38) - PAGE_SIZE, PAGE_SIZE, 0b011),
+        Ok((1 << 38) - PAGE_SIZE)
+    );
+    info!("mmap_canonical_test passed!");
+}
+
+pub fn remove_area_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let permission = MapPermission::R | MapPermission::W | MapPermission::U;
+    let first = VirtAddr::from(0x10000);
+    let second = VirtAddr::from(0x20000);
+    memory_set.insert_framed_area(first, (first.0 + 2 * PAGE_SIZE).into(), permission);
+    memory_set.insert_framed_area(second, (second.0 + PAGE_SIZE).into(), permission);
+    assert_eq!(memory_set.resident_frames(), 3);
+    // 只有逻辑段的起始虚拟页号才能匹配
+    assert!(!memory_set.remove_area_with_start_vpn(VirtPageNum(first.floor().0 + 1)));
+    assert!(memory_set.remove_area_with_start_vpn(first.floor()));
+    assert!(!memory_set.remove_area_with_start_vpn(first.floor()));
+    assert_eq!(memory_set.resident_frames(), 1);
+    assert!(memory_set
+        .translate(first.floor())
+        .map_or(true, |pte| !pte.is_valid()));
+    assert!(memory_set.translate(second.floor()).unwrap().is_valid());
+    info!("remove_area_test passed!");
+}
+
+pub fn verify_permissions_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 0b001), Ok(start));
+    assert!(memory_set.verify_permissions());
+    // 让其中一页的页表项变为可写，与逻辑段记录的只读权限不一致
+    let vpn = VirtAddr::from(start + PAGE_SIZE).floor();
+    let ppn = memory_set.translate(vpn).unwrap().ppn();
+    memory_set
+        .page_table
+        .remap(vpn, ppn, PTEFlags::R | PTEFlags::W | PTEFlags::U);
+    assert!(!memory_set.verify_permissions());
+    info!("verify_permissions_test passed!");
+}
+
+pub fn global_mapping_test() {
+    let global = |memory_set: &MemorySet, vpn: VirtPageNum| {
+        memory_set
+            .translate(vpn)
+            .unwrap()
+            .flags()
+            .contains(PTEFlags::G)
+    };
+    let trampoline = VirtAddr::from(TRAMPOLINE).floor();
+    // 内核的恒等映射不存在于应用地址空间中，不能设置 G 位
+    let kernel_space = KERNEL_SPACE.lock();
+    let kernel_addrs = [
+        stext as usize,
+        srodata as usize,
+        sdata as usize,
+        ekernel as usize,
+    ];
+    for &va in kernel_addrs.iter() {
+        assert!(!global(&kernel_space, VirtAddr::from(va).floor()));
+    }
+    // 跳板在每个地址空间中都相同
+    assert!(global(&kernel_space, trampoline));
+    drop(kernel_space);
+    let (mut memory_set, _, _) = MemorySet::from_elf(crate::loader::get_app_data(0)).unwrap();
+    assert!(global(&memory_set, trampoline));
+    let start = memory_set.find_free_range(VirtPageNum(0x10000), 1).unwrap();
+    let va = VirtAddr::from(start).0;
+    assert_eq!(memory_set.mmap(va, PAGE_SIZE, 0b011), Ok(va));
+    assert!(!global(&memory_set, start));
+    info!("global_mapping_test passed!");
+}
+
+pub fn mmap_hint_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(
+        memory_set.mmap(start + 3 * PAGE_SIZE, PAGE_SIZE, 0b011),
+        Ok(start + 3 * PAGE_SIZE)
+    );
+    // 固定地址时重叠直接失败
+    assert_eq!(
+        memory_set.mmap(start, PAGE_SIZE, 0b011),
+        Err(MmapError::Overlap)
+    );
+    // 建议地址时跳过已有映射，两页的区域放不进 [start + 2, start + 3) 的空隙
+    let hint = MmapFlags::HINT;
+    assert_eq!(
+        memory_set.mmap_with(start, 2 * PAGE_SIZE, 0b011, hint),
+        Ok(start + 4 * PAGE_SIZE)
+    );
+    assert_eq!(
+        memory_set.mmap_with(start, PAGE_SIZE, 0b011, hint),
+        Ok(start + 2 * PAGE_SIZE)
+    );
+    // 建议地址本身空闲时直接使用
+    assert_eq!(
+        memory_set.mmap_with(0x20000, PAGE_SIZE, 0b011, hint),
+        Ok(0x20000)
+    );
+    // port 中的第 5 位不再表示建议地址
+    assert_eq!(
+        memory_set.mmap(start, PAGE_SIZE, 0b100011),
+        Err(MmapError::InvalidPort)
+    );
+    info!("mmap_hint_test passed!");
+}
+
+pub fn find_free_range_test() {
+    let limit = VirtAddr::from(TRAP_CONTEXT).floor();
+    let hint = VirtPageNum(0x10);
+    // 没有任何逻辑段时直接返回 hint
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(memory_set.find_free_range(hint, 4), Some(hint));
+    // [0x10, 0x12) 和 [0x15, 0x16) 之间恰好有 3 页的空隙
+    assert_eq!(memory_set.mmap(0x10000, 2 * PAGE_SIZE, 0b011), Ok(0x10000));
+    assert_eq!(memory_set.mmap(0x15000, PAGE_SIZE, 0b011), Ok(0x15000));
+    assert_eq!(memory_set.find_free_range(hint, 3), Some(VirtPageNum(0x12)));
+    assert_eq!(
+        memory_set.find_free_range(VirtPageNum(0x11), 3),
+        Some(VirtPageNum(0x12))
+    );
+    assert_eq!(memory_set.find_free_range(hint, 4), Some(VirtPageNum(0x16)));
+    // 保护页面也算作已占用
+    assert_eq!(
+        memory_set.mmap_with(0x20000, PAGE_SIZE, 0b011, MmapFlags::GUARD),
+        Ok(0x20000)
+    );
+    assert_eq!(
+        memory_set.find_free_range(VirtPageNum(0x20), 1),
+        Some(VirtPageNum(0x22))
+    );
+    // Trap 上下文之下没有足够的空间
+    assert_eq!(
+        memory_set.find_free_range(VirtPageNum(limit.0 - 2), 3),
+        None
+    );
+    assert_eq!(
+        memory_set.find_free_range(VirtPageNum(limit.0 - 2), 2),
+        Some(VirtPageNum(limit.0 - 2))
+    );
+    assert_eq!(memory_set.find_free_range(hint, usize::MAX), None);
+    info!("find_free_range_test passed!");
+}
+
+pub fn madvise_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    assert_eq!(memory_set.mmap(start, 3 * PAGE_SIZE, 0b011), Ok(start));
+    // 第 i 页已映射时返回其物理页帧的内容
+    let page = |memory_set: &MemorySet, i: usize| {
+        memory_set
+            .translate(VirtAddr::from(start + i * PAGE_SIZE).floor())
+            .filter(|pte| pte.is_valid())
+            .map(|pte| pte.ppn().get_bytes_array())
+    };
+    for i in 0..3 {
+        page(&memory_set, i).unwrap().fill(0x5a);
+    }
+    let frames = memory_set.resident_frames();
+    // 丢弃后两页的页帧，逻辑段仍然保留
+    assert_eq!(
+        memory_set.madvise_dontneed(start + PAGE_SIZE, 2 * PAGE_SIZE),
+        Ok(())
+    );
+    assert_eq!(memory_set.resident_frames(), frames - 2);
+    assert!(page(&memory_set, 1).is_none() && page(&memory_set, 2).is_none());
+    assert!(memory_set
+        .find_area(VirtAddr::from(start + 2 * PAGE_SIZE).floor())
+        .is_some());
+    // 丢弃了页帧的页面仍然属于原来的逻辑段，不能被再次映射
+    let areas = memory_set.areas.len();
+    assert_eq!(
+        memory_set.mmap(start + 2 * PAGE_SIZE, PAGE_SIZE, 0b011),
+        Err(MmapError::Overlap)
+    );
+    assert_eq!(
+        memory_set.mmap(start + 2 * PAGE_SIZE, 2 * PAGE_SIZE, 0b011),
+        Err(MmapError::Overlap)
+    );
+    assert_eq!(memory_set.areas.len(), areas);
+    // 再次访问时缺页异常分配清零的页帧，未被丢弃的页面保持原有内容
+    assert!(memory_set.handle_page_fault(VirtAddr::from(start + PAGE_SIZE + 8)));
+    assert!(page(&memory_set, 1).unwrap().iter().all(|&b| b == 0));
+    assert!(page(&memory_set, 0).unwrap().iter().all(|&b| b == 0x5a));
+    // 已有页帧或不属于任何逻辑段的页面不由缺页异常处理
+    assert!(!memory_set.handle_page_fault(VirtAddr::from(start)));
+    assert!(!memory_set.handle_page_fault(VirtAddr::from(start + 3 * PAGE_SIZE)));
+    // 范围内有未映射的页面时整体失败
+    assert_eq!(
+        memory_set.madvise_dontneed(start, 4 * PAGE_SIZE),
+        Err(MmapError::NotMapped)
+    );
+    assert!(page(&memory_set, 0).is_some());
+    // 丢弃了页帧的页面仍然可以取消映射
+    assert_eq!(memory_set.munmap(start, 3 * PAGE_SIZE), Ok(()));
+    assert!(memory_set
+        .find_area(VirtAddr::from(start).floor())
+        .is_none());
+    info!("madvise_test passed!");
+}
+
+pub fn madvise_reserved_test() {
+    let (mut memory_set, _, _) = MemorySet::from_elf(crate::loader::get_app_data(0)).unwrap();
+    let trap_cx_vpn = VirtAddr::from(TRAP_CONTEXT).floor();
+    let ppn = memory_set.translate(trap_cx_vpn).unwrap().ppn();
+    let frames = memory_set.resident_frames();
+    assert_eq!(
+        memory_set.madvise_dontneed(TRAP_CONTEXT, PAGE_SIZE),
+        Err(MmapError::Reserved)
+    );
+    assert_eq!(
+        memory_set.madvise_dontneed(TRAMPOLINE, PAGE_SIZE),
+        Err(MmapError::Reserved)
+    );
+    // Trap 上下文的页帧没有被回收，仍然映射在原来的位置
+    assert_eq!(memory_set.resident_frames(), frames);
+    let pte = memory_set.translate(trap_cx_vpn).unwrap();
+    assert!(pte.is_valid() && !pte.flags().contains(PTEFlags::U));
+    assert_eq!(pte.ppn(), ppn);
+    info!("madvise_reserved_test passed!");
+}
+
+pub fn munmap_len_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    let mapped = |memory_set: &MemorySet, i: usize| {
+        memory_set
+            .translate(VirtAddr::from(start + i * PAGE_SIZE).floor())
+            .map_or(false, |pte| pte.is_valid())
+    };
+    assert_eq!(memory_set.mmap(start, 3 * PAGE_SIZE, 0b011), Ok(start));
+    // len 为 0 时报错，且不影响已有映射
+    assert_eq!(memory_set.munmap(start, 0), Err(MmapError::InvalidRange));
+    assert!(mapped(&memory_set, 0));
+    // len 不是页面大小的整数倍时向上取整到整页
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE + 1), Ok(()));
+    assert!(!mapped(&memory_set, 0) && !mapped(&memory_set, 1));
+    assert!(mapped(&memory_set, 2));
+    // 向上取整后覆盖到未映射的页面时整体失败
+    assert_eq!(
+        memory_set.munmap(start + 2 * PAGE_SIZE, PAGE_SIZE + 1),
+        Err(MmapError::NotMapped)
+    );
+    assert!(mapped(&memory_set, 2));
+    info!("munmap_len_test passed!");
+}
+
+pub fn range_is_mapped_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    let rw = MapPermission::R | MapPermission::W | MapPermission::U;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(
+        memory_set.mmap(start + 3 * PAGE_SIZE, PAGE_SIZE, 0b001),
+        Ok(start + 3 * PAGE_SIZE)
+    );
+    // 完整映射的范围，不要求按页对齐
+    assert!(memory_set.range_is_mapped(start, 2 * PAGE_SIZE, rw));
+    assert!(memory_set.range_is_mapped(start + 8, PAGE_SIZE, rw));
+    // 中间有未映射的页面
+    assert!(!memory_set.range_is_mapped(start, 4 * PAGE_SIZE, MapPermission::R));
+    assert!(!memory_set.range_is_mapped(start + 2 * PAGE_SIZE, 1, MapPermission::empty()));
+    // 只读页面缺少写权限
+    let read_only = start + 3 * PAGE_SIZE;
+    assert!(memory_set.range_is_mapped(read_only, PAGE_SIZE, MapPermission::R | MapPermission::U));
+    assert!(!memory_set.range_is_mapped(read_only, PAGE_SIZE, rw));
+    assert!(!memory_set.range_is_mapped(start, usize::MAX, MapPermission::R));
+    info!("range_is_mapped_test passed!");
+}
+
+pub fn map_physical_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+    let (va, pa) = (VirtAddr::from(0x10000), PhysAddr::from(0x1000_0000));
+    assert_eq!(memory_set.map_physical(va, pa, perm), 0);
+    let pte = memory_set.translate(va.floor()).unwrap();
+    assert!(pte.is_valid());
+    assert_eq!(pte.ppn(), pa.floor());
+    assert_eq!(MapPermission::from(pte.flags()), perm);
+    // 已经映射的页面、未对齐的地址和内核的物理内存都会被拒绝
+    assert_eq!(memory_set.map_physical(va, pa, perm), -1);
+    let next = VirtAddr::from(0x11000);
+    assert_eq!(
+        memory_set.map_physical(next, PhysAddr::from(0x1000_0001), perm),
+        -1
+    );
+    let kernel_pa = PhysAddr::from(MEMORY_END - PAGE_SIZE);
+    assert_eq!(memory_set.map_physical(next, kernel_pa, perm), -1);
+    assert_eq!(
+        memory_set.map_physical(VirtAddr::from(TRAP_CONTEXT), pa, perm),
+        -1
+    );
+    // 取消映射时不会把并不持有的页帧还给页帧分配器
+    assert_eq!(memory_set.munmap(va.0, PAGE_SIZE), Ok(()));
+    assert!(memory_set
+        .translate(va.floor())
+        .map_or(true, |pte| !pte.is_valid()));
+    info!("map_physical_test passed!");
+}
+
+pub fn shared_segment_test() {
+    let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+    let segment = SharedSegment::new(2).unwrap();
+    let mut first = MemorySet::new_bare();
+    let mut second = MemorySet::new_bare();
+    let (va_first, va_second) = (VirtAddr::from(0x10000), VirtAddr::from(0x40000));
+    assert_eq!(first.attach_shared(va_first, &segment, perm), 0);
+    assert_eq!(second.attach_shared(va_second, &segment, perm), 0);
+    // 重复映射到已被占用的页面
+    assert_eq!(
+        second.attach_shared(VirtAddr::from(0x41000), &segment, perm),
+        -1
+    );
+    // 两个地址空间中对应的页面映射到同一个物理页帧
+    let page = |memory_set: &MemorySet, va: VirtAddr, i: usize| {
+        let vpn = VirtPageNum(va.floor().0 + i);
+        memory_set.translate(vpn).unwrap().ppn().get_bytes_array()
+    };
+    page(&first, va_first, 1)[8] = 0x5a;
+    assert_eq!(page(&second, va_second, 1)[8], 0x5a);
+    // 共享段和其中一个地址空间都被回收后，页帧仍然由另一个地址空间持有
+    let frames = second.resident_frames();
+    drop(segment);
+    drop(first);
+    assert_eq!(second.resident_frames(), frames);
+    assert_eq!(page(&second, va_second, 1)[8], 0x5a);
+    info!("shared_segment_test passed!");
+}
+
+pub fn translate_kernel_test() {
+    let kernel_space = KERNEL_SPACE.lock();
+    let addrs = [
+        stext as usize,
+        etext as usize - 1,
+        (srodata as usize + erodata as usize) / 2,
+        sdata as usize,
+        ebss as usize - 1,
+        MEMORY_END - HUGE_PAGE_SIZE / 2 + PAGE_SIZE,
+    ];
+    for addr in addrs {
+        let vpn = VirtAddr::from(addr).floor();
+        assert!(kernel_space.is_identical(vpn));
+        let ppn = kernel_space.translate(vpn).unwrap().ppn();
+        assert_eq!(kernel_space.translate_kernel(vpn), Some(ppn));
+    }
+    // 跳板不是恒等映射，走查页表的路径
+    let trampoline = VirtAddr::from(TRAMPOLINE).floor();
+    assert!(!kernel_space.is_identical(trampoline));
+    assert_eq!(
+        kernel_space.translate_kernel(trampoline),
+        Some(PhysAddr::from(strampoline as usize).floor())
+    );
+    info!("translate_kernel_test passed!");
+}
+
+pub fn permission_display_test() {
+    let rw = MapPermission::R | MapPermission::W | MapPermission::U;
+    assert_eq!(format!("{}", rw), "rw-u");
+    assert_eq!(format!("{}", MapPermission::R | MapPermission::X), "r-x-");
+    assert_eq!(format!("{}", MapPermission::all()), "rwxu");
+    assert_eq!(format!("{}", MapPermission::empty()), "----");
+    assert_eq!(format!("{}", MapType::Framed), "Framed");
+    assert_eq!(format!("{}", MapType::Direct(PhysPageNum(0x10))), "Direct");
+    assert_eq!(format!("{:?}", MapType::HugePage), "HugePage");
+    info!("permission_display_test passed!");
+}
+
+pub fn virtual_size_test() {
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(memory_set.virtual_size(), 0);
+    let start = 0x10000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 0b011), Ok(start));
+    // 不足一页的部分按整页计算，保护页面不计入
+    let second = start + 4 * PAGE_SIZE;
+    assert_eq!(
+        memory_set.mmap_with(second, 2 * PAGE_SIZE + 1, 0b011, MmapFlags::GUARD),
+        Ok(second)
+    );
+    assert_eq!(memory_set.virtual_size(), 5 * PAGE_SIZE);
+    // 丢弃页帧只减少实际占用的页帧，不改变虚拟地址空间的大小
+    assert_eq!(memory_set.madvise_dontneed(start, PAGE_SIZE), Ok(()));
+    assert_eq!(memory_set.resident_frames(), 4);
+    assert_eq!(memory_set.virtual_size(), 5 * PAGE_SIZE);
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), Ok(()));
+    assert_eq!(memory_set.virtual_size(), 3 * PAGE_SIZE);
+    info!("virtual_size_test passed!");
+}
+
+pub fn copy_data_length_test() {
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        VirtAddr::from(0x10000),
+        VirtAddr::from(0x12000),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    memory_set.insert_framed_area(
+        VirtAddr::from(0x20000),
+        VirtAddr::from(0x20000),
+        MapPermission::R | MapPermission::U,
+    );
+    let page_table = &mut memory_set.page_table;
+    let mut areas = memory_set.areas.values_mut();
+    let area = areas.next().unwrap();
+    let dirty = |area: &MapArea| {
+        for frame in area.data_frames.values() {
+            frame.ppn.get_bytes_array().fill(0xff);
+        }
+    };
+    let page = |area: &MapArea, i: usize| area.data_frames[&VirtPageNum(0x10 + i)].ppn;
+    // 没有数据时两页都被清零
+    dirty(area);
+    area.copy_data(page_table, &[]);
+    assert!((0..2).all(|i| page(area, i).get_bytes_array().iter().all(|&b| b == 0)));
+    // 恰好一页数据
+    dirty(area);
+    let data = [0x5au8; 2 * PAGE_SIZE];
+    area.copy_data(page_table, &data[..PAGE_SIZE]);
+    assert!(page(area, 0).get_bytes_array().iter().all(|&b| b == 0x5a));
+    assert!(page(area, 1).get_bytes_array().iter().all(|&b| b == 0));
+    // 恰好两页数据
+    dirty(area);
+    area.copy_data(page_table, &data);
+    assert!((0..2).all(|i| page(area, i).get_bytes_array().iter().all(|&b| b == 0x5a)));
+    // 空逻辑段拷贝空数据
+    let empty = areas.next().unwrap();
+    empty.copy_data(page_table, &[]);
+    info!("copy_data_length_test passed!");
+}
+
+pub fn copy_region_from_test() {
+    let mut target = MemorySet::new_bare();
+    let tracer = MemorySet::new_bare();
+    let start = 0x10000;
+    assert_eq!(target.mmap(start, 2 * PAGE_SIZE, 0b011), Ok(start));
+    // 写入一段跨越页面边界的数据
+    let offset = PAGE_SIZE - 4;
+    let pattern: Vec<u8> = (0..8u8).map(|i| 0xa0 + i).collect();
+    for (i, byte) in pattern.iter().enumerate() {
+        let va = VirtAddr::from(start + offset + i);
+        let ppn = target.translate(va.floor()).unwrap().ppn();
+        ppn.get_bytes_array()[va.page_offset()] = *byte;
+    }
+    assert_eq!(
+        tracer.copy_region_from(&target, start + offset, 8),
+        Some(pattern)
+    );
+    assert_eq!(tracer.copy_region_from(&target, start, 0), Some(Vec::new()));
+    // 范围中有未映射的页面
+    assert_eq!(
+        tracer.copy_region_from(&target, start + PAGE_SIZE, 2 * PAGE_SIZE),
+        None
+    );
+    assert_eq!(tracer.copy_region_from(&target, start, usize::MAX), None);
+    // 只是读取数据，tracer 自身的地址空间不受影响
+    assert!(tracer.translate(VirtAddr::from(start).floor()).is_none());
+    info!("copy_region_from_test passed!");
+}
+
+pub fn mprotect_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    let guard = start + PAGE_SIZE;
+    assert_eq!(memory_set.mmap(start, 3 * PAGE_SIZE, 0b011), Ok(start));
+    // 中间一页设为不允许访问，页帧和映射仍然保留
+    assert_eq!(memory_set.mprotect(guard, PAGE_SIZE, 0), Ok(()));
+    let pte = memory_set.translate(VirtAddr::from(guard).floor()).unwrap();
+    assert!(pte.is_valid() && !pte.user_readable() && !pte.user_writable());
+    let ppn = pte.ppn();
+    let area = memory_set.find_area(VirtAddr::from(guard).floor()).unwrap();
+    assert_eq!(area.map_permission, MapPermission::R);
+    assert_eq!(area.frame_count(), 1);
+    assert!(!memory_set.handle_page_fault(VirtAddr::from(guard)));
+    // 两侧的页面不受影响
+    let rw = MapPermission::R | MapPermission::W | MapPermission::U;
+    assert!(memory_set.range_is_mapped(start, PAGE_SIZE, rw));
+    assert!(memory_set.range_is_mapped(guard + PAGE_SIZE, PAGE_SIZE, rw));
+    assert!(memory_set.verify_permissions());
+    // 恢复访问权限后仍然使用原来的页帧
+    assert_eq!(memory_set.mprotect(guard, PAGE_SIZE, 0b001), Ok(()));
+    let pte = memory_set.translate(VirtAddr::from(guard).floor()).unwrap();
+    assert!(pte.user_readable() && !pte.user_writable());
+    assert_eq!(pte.ppn(), ppn);
+    assert_eq!(
+        memory_set.mprotect(guard + 1, PAGE_SIZE, 0),
+        Err(MmapError::Unaligned)
+    );
+    assert_eq!(
+        memory_set.mprotect(guard, PAGE_SIZE, 0b1000),
+        Err(MmapError::InvalidPort)
+    );
+    assert_eq!(
+        memory_set.mprotect(start, 4 * PAGE_SIZE, 0),
+        Err(MmapError::NotMapped)
+    );
+    assert_eq!(memory_set.munmap(start, 3 * PAGE_SIZE), Ok(()));
+    info!("mprotect_test passed!");
+}
+
+pub fn mprotect_reserved_test() {
+    let (mut memory_set, _, _) = MemorySet::from_elf(crate::loader::get_app_data(0)).unwrap();
+    let trap_cx_vpn = VirtAddr::from(TRAP_CONTEXT).floor();
+    let ppn = memory_set.translate(trap_cx_vpn).unwrap().ppn();
+    // Trap 上下文和跳板只属于内核，不能被应用修改权限
+    assert_eq!(
+        memory_set.mprotect(TRAP_CONTEXT, PAGE_SIZE, 0b111),
+        Err(MmapError::Reserved)
+    );
+    assert_eq!(
+        memory_set.mprotect(TRAMPOLINE, PAGE_SIZE, 0b111),
+        Err(MmapError::Reserved)
+    );
+    // 范围的一部分覆盖 Trap 上下文时同样整体失败
+    assert_eq!(
+        memory_set.mprotect(TRAP_CONTEXT - PAGE_SIZE, 2 * PAGE_SIZE, 0b011),
+        Err(MmapError::Reserved)
+    );
+    let pte = memory_set.translate(trap_cx_vpn).unwrap();
+    assert!(pte.is_valid() && !pte.flags().contains(PTEFlags::U));
+    assert_eq!(pte.ppn(), ppn);
+    assert!(memory_set.verify_permissions());
+    info!("mprotect_reserved_test passed!");
+}
+
+pub fn serialize_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    let data = start + 4 * PAGE_SIZE;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(memory_set.mmap(data, PAGE_SIZE, 0b001), Ok(data));
+    let vpn = VirtAddr::from(data).floor();
+    let ppn = memory_set.translate(vpn).unwrap().ppn();
+    ppn.get_bytes_array()[..4].copy_from_slice(b"ckpt");
+    // 恒等映射的逻辑段只记录范围，不复制内容
+    let identical = VirtAddr::from(MEMORY_END - PAGE_SIZE);
+    let perm = MapPermission::R | MapPermission::W;
+    let area = MapArea::new(identical, MEMORY_END.into(), MapType::Identical, perm);
+    memory_set.push(area, None);
+    let snapshot = memory_set.serialize();
+    drop(memory_set);
+    assert_eq!(snapshot.len(), 3);
+    assert_eq!(snapshot[0].pages.len(), 2);
+    assert_eq!(snapshot[1].start, vpn);
+    assert_eq!(snapshot[1].map_type, MapType::Framed);
+    assert!(!snapshot[1].fresh);
+    assert_eq!(&snapshot[1].pages[0].1[..4], b"ckpt");
+    assert!(snapshot[1].pages[0].1[4..].iter().all(|&b| b == 0));
+    assert!(snapshot[2].fresh && snapshot[2].pages.is_empty());
+    info!("serialize_test passed!");
+}
+
+pub fn from_snapshot_test() {
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.map_trampoline();
+    let start = 0x10000;
+    assert_eq!(memory_set.mmap(start, 3 * PAGE_SIZE, 0b011), Ok(start));
+    let vpn = VirtAddr::from(start + PAGE_SIZE).floor();
+    let ppn = memory_set.translate(vpn).unwrap().ppn();
+    ppn.get_bytes_array()[..7].copy_from_slice(b"restore");
+    // 被丢弃了页帧的页面在恢复之后仍然没有页帧
+    let dropped = start + 2 * PAGE_SIZE;
+    assert_eq!(memory_set.madvise_dontneed(dropped, PAGE_SIZE), Ok(()));
+    let snapshot = memory_set.serialize();
+
+    let mut restored = MemorySet::from_snapshot(&snapshot);
+    assert_eq!(restored.describe(), memory_set.describe());
+    let pte = restored.translate(vpn).unwrap();
+    assert!(pte.is_valid() && pte.user_writable());
+    assert_ne!(pte.ppn(), ppn);
+    assert_eq!(&pte.ppn().get_bytes_array()[..7], b"restore");
+    assert_eq!(restored.resident_frames(), memory_set.resident_frames());
+    assert!(!restored.range_is_mapped(dropped, PAGE_SIZE, MapPermission::empty()));
+    assert!(restored.handle_page_fault(VirtAddr::from(dropped)));
+    let trampoline = VirtAddr::from(TRAMPOLINE).floor();
+    assert_eq!(
+        restored.translate(trampoline).unwrap().ppn(),
+        memory_set.translate(trampoline).unwrap().ppn()
+    );
+    // 恢复得到的地址空间使用独立的页帧
+    pte.ppn().get_bytes_array()[0] = b'R';
+    assert_eq!(ppn.get_bytes_array()[0], b'r');
+    info!("from_snapshot_test passed!");
+}
+
+pub fn map_area_eq_test() {
+    let (start, end) = (VirtAddr::from(0x10000), VirtAddr::from(0x12000));
+    let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+    let mut page_table = PageTable::new();
+    let mut mapped = MapArea::new(start, end, MapType::Framed, perm);
+    mapped.map(&mut page_table);
+    let unmapped = MapArea::new(start, end, MapType::Framed, perm);
+    // 只比较范围、映射方式和访问权限，与是否已经分配页帧无关
+    assert_eq!(mapped, unmapped);
+    assert_ne!(
+        mapped,
+        MapArea::new(start, end, MapType::Framed, MapPermission::R)
+    );
+    assert_ne!(mapped, MapArea::new(start, end, MapType::Identical, perm));
+    assert_ne!(
+        mapped,
+        MapArea::new(start, VirtAddr::from(0x11000), MapType::Framed, perm)
+    );
+    let debug = format!("{:?}", mapped);
+    assert!(debug.contains("start: VPN:0x10, end: VPN:0x12"));
+    assert!(debug.contains("frames: 2"));
+    mapped.unmap(&mut page_table);
+    info!("map_area_eq_test passed!");
+}
+
+pub fn mmap_oom_rollback_test() {
+    // 只有调试构建中 set_alloc_fail_after 才会生效
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b011), Ok(start));
+    let (layout, resident) = (memory_set.describe(), memory_set.resident_frames());
+    // 映射到一半时页帧耗尽
+    let other = start + 4 * PAGE_SIZE;
+    super::set_alloc_fail_after(2);
+    assert_eq!(
+        memory_set.mmap(other, 4 * PAGE_SIZE, 0b011),
+        Err(MmapError::OutOfMemory)
+    );
+    super::set_alloc_fail_after(usize::MAX);
+    assert_eq!(memory_set.describe(), layout);
+    assert_eq!(memory_set.resident_frames(), resident);
+    assert!(!memory_set.range_is_mapped(other, PAGE_SIZE, MapPermission::empty()));
+    // 恢复之后同一段区域可以正常映射
+    assert_eq!(memory_set.mmap(other, 4 * PAGE_SIZE, 0b011), Ok(other));
+    info!("mmap_oom_rollback_test passed!");
+}
+
+pub fn trace_mappings_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    set_trace_mappings(true);
+    assert_eq!(memory_set.mmap(start, 3 * PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(mapping_events(), (3, 0));
+    // 部分取消映射时只记录被取消映射的页面，map 与 unmap 事件最终一一对应
+    assert_eq!(memory_set.munmap(start + PAGE_SIZE, PAGE_SIZE), Ok(()));
+    assert_eq!(mapping_events(), (3, 1));
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE), Ok(()));
+    assert_eq!(memory_set.munmap(start + 2 * PAGE_SIZE, PAGE_SIZE), Ok(()));
+    assert_eq!(mapping_events(), (3, 3));
+    set_trace_mappings(false);
+    // 关闭之后不再记录
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(mapping_events(), (3, 3));
+    info!("trace_mappings_test passed!");
+}
+
+pub fn mmap_zero_fill_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    let flags = MmapFlags::ZERO_FILL;
+    assert_eq!(
+        memory_set.mmap_with(start, 3 * PAGE_SIZE, 0b011, flags),
+        Ok(start)
+    );
+    for vpn in VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x13)) {
+        let bytes = memory_set.translate(vpn).unwrap().ppn().get_bytes_array();
+        assert!(bytes.iter().all(|&b| b == 0));
+    }
+    info!("mmap_zero_fill_test passed!");
+}
+
+pub fn mmap_guard_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    let guard = start + PAGE_SIZE;
+    assert_eq!(
+        memory_set.mmap_with(start, PAGE_SIZE, 0b011, MmapFlags::GUARD),
+        Ok(start)
+    );
+    // port 中的第 4 位不再表示保护页面
+    assert_eq!(
+        memory_set.mmap(guard + PAGE_SIZE, PAGE_SIZE, 0b10011),
+        Err(MmapError::InvalidPort)
+    );
+    // 保护页面之上相隔一页的区域可以映射，保护页面本身不行
+    assert_eq!(
+        memory_set.mmap(guard + PAGE_SIZE, PAGE_SIZE, 0b011),
+        Ok(guard + PAGE_SIZE)
+    );
+    assert_eq!(
+        memory_set.mmap(guard, PAGE_SIZE, 0b011),
+        Err(MmapError::Overlap)
+    );
+    // 保护页面始终没有映射，访问时不会被缺页异常补上
+    assert!(!memory_set.range_is_mapped(guard, PAGE_SIZE, MapPermission::empty()));
+    assert!(!memory_set.handle_page_fault(VirtAddr::from(guard)));
+    // 新的区域的保护页面不能落在已有的区域上
+    assert_eq!(
+        memory_set.mmap_with(guard + 2 * PAGE_SIZE, PAGE_SIZE, 0b011, MmapFlags::GUARD),
+        Ok(guard + 2 * PAGE_SIZE)
+    );
+    assert_eq!(
+        memory_set.mmap_with(start - PAGE_SIZE, PAGE_SIZE, 0b011, MmapFlags::GUARD),
+        Err(MmapError::Overlap)
+    );
+    info!("mmap_guard_test passed!");
+}
@@ -15,11 +15,19 @@ mod page_table;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, FrameTracker};
+pub use frame_allocator::{frame_alloc, frame_dealloc_bulk, set_alloc_fail_after, FrameTracker};
+pub use frame_allocator::{FrameAllocator, StackFrameAllocator};
 pub use memory_set::remap_test;
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
-pub use page_table::{translated_byte_buffer, PageTableEntry};
-pub use page_table::{PTEFlags, PageTable};
+pub use memory_set::{
+    set_trace_mappings, AreaSnapshot, MapPermission, MemorySet, MmapError, MmapFlags,
+    SharedSegment, KERNEL_SPACE,
+};
+pub use page_table::{
+    copy_from_user, copy_to_user, translated_byte_buffer, translated_byte_buffer_checked,
+    translated_byte_buffer_ref, translated_byte_buffer_writable, translated_str, PageTableEntry,
+    UserBuffer,
+};
+pub use page_table::{flush_tlb, PTEFlags, PageTable};
 
 /// initiate heap allocator, frame allocator and kernel space
 pub fn init() {
@@ -27,3 +35,75 @@ pub fn init() {
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.lock().activate();
 }
+
+/// 依次运行内存管理相关的测试，需要在 init 之后调用
+pub fn run_tests() {
+    heap_allocator::heap_test();
+    frame_allocator::frame_allocator_test();
+    frame_allocator::frame_reserve_test();
+    frame_allocator::recycle_policy_test();
+    frame_allocator::frame_dealloc_bulk_test();
+    frame_allocator::frame_exhaustion_test();
+    frame_allocator::stack_frame_allocator_test();
+    frame_allocator::frame_poison_test();
+    address::address_test();
+    address::simple_range_test();
+    address::get_mut_array_test();
+    address::range_end_test();
+    address::to_vpn_checked_test();
+    page_table::page_table_test();
+    page_table::huge_page_test();
+    page_table::copy_to_user_test();
+    page_table::copy_from_user_test();
+    page_table::translated_str_test();
+    page_table::canonical_test();
+    page_table::translated_ppn_range_test();
+    page_table::user_buffer_test();
+    page_table::user_buffer_write_test();
+    page_table::from_token_test();
+    page_table::translate_user_test();
+    page_table::translated_byte_buffer_ref_test();
+    page_table::translated_phys_spans_test();
+    memory_set::mmap_bounds_test();
+    memory_set::iter_mappings_test();
+    memory_set::overlap_test();
+    memory_set::elf_bounds_test();
+    memory_set::elf_arch_test();
+    memory_set::copy_data_test();
+    memory_set::copy_data_checked_test();
+    memory_set::munmap_partial_test();
+    memory_set::split_test();
+    memory_set::coalesce_test();
+    memory_set::find_area_test();
+    memory_set::scan_accessed_test();
+    memory_set::permission_conversion_test();
+    memory_set::mmap_error_test();
+    memory_set::populate_test();
+    memory_set::mmap_reserved_test();
+    memory_set::mmap_canonical_test();
+    memory_set::remove_area_test();
+    memory_set::verify_permissions_test();
+    memory_set::global_mapping_test();
+    memory_set::mmap_hint_test();
+    memory_set::find_free_range_test();
+    memory_set::madvise_test();
+    memory_set::madvise_reserved_test();
+    memory_set::munmap_len_test();
+    memory_set::range_is_mapped_test();
+    memory_set::map_physical_test();
+    memory_set::shared_segment_test();
+    memory_set::translate_kernel_test();
+    memory_set::permission_display_test();
+    memory_set::virtual_size_test();
+    memory_set::copy_data_length_test();
+    memory_set::copy_region_from_test();
+    memory_set::mprotect_test();
+    memory_set::mprotect_reserved_test();
+    memory_set::serialize_test();
+    memory_set::from_snapshot_test();
+    memory_set::map_area_eq_test();
+    memory_set::mmap_oom_rollback_test();
+    memory_set::trace_mappings_test();
+    memory_set::mmap_zero_fill_test();
+    memory_set::mmap_guard_test();
+}
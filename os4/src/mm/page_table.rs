@@ -1,9 +1,13 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 
-use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use super::PhysAddr;
+use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VPNRange, VirtAddr, VirtPageNum};
+use crate::config::{HUGE_PAGE_SIZE, MEMORY_END, PAGE_SIZE};
+use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use riscv::register::satp;
 
 // SV39 分页模式下的页表项，[53: 10] 这 44 位是物理页号，最低的 8 位 [7: 0] 是标志位，含义如下：
 // 仅当 V(Valid) 位为 1 时，页表项才是合法的；
@@ -11,10 +15,11 @@ use bitflags::*;
 // U 控制索引到这个页表项的对应虚拟页面是否在 CPU 处于 U 特权级的情况下是否被允许访问；
 // G 我们不理会；
 // A(Accessed) 记录自从页表项上的这一位被清零之后，页表项的对应虚拟页面是否被访问过；
-// D(Dirty) 则记录自从页表项上的这一位被清零之后，页表项的对应虚拟页表是否被修改过。
+// D(Dirty) 则记录自从页表项上的这一位被清零之后，页表项的对应虚拟页表是否被修改过；
+// [9: 8] 两位 RSW 留给软件使用，硬件不会理会，可以用来记录写时复制等状态。
 bitflags! {
     /// page table entry flags
-    pub struct PTEFlags: u8 {
+    pub struct PTEFlags: u16 {
         const V = 1 << 0;
         const R = 1 << 1;
         const W = 1 << 2;
@@ -23,6 +28,8 @@ bitflags! {
         const G = 1 << 5;
         const A = 1 << 6;
         const D = 1 << 7;
+        const RSW0 = 1 << 8;
+        const RSW1 = 1 << 9;
     }
 }
 
@@ -53,9 +60,9 @@ impl PageTableEntry {
         (self.bits >> 10 & ((1usize << 44) - 1)).into()
     }
 
-    // 根据页表项得到标志位 [7: 0] 共 8 位
+    // 根据页表项得到标志位 [9: 0] 共 10 位，其余位被忽略而不会 panic
     pub fn flags(&self) -> PTEFlags {
-        PTEFlags::from_bits(self.bits as u8).unwrap()
+        PTEFlags::from_bits_truncate(self.bits as u16)
     }
 
     /* 一些辅助函数 */
@@ -79,6 +86,42 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+
+    // 判断该页面是否合法且允许用户态读取，即 V/U/R 均为 1
+    pub fn user_readable(&self) -> bool {
+        self.is_valid() && self.readable() && (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
+
+    // 判断该页面是否合法且允许用户态写入
+    pub fn user_writable(&self) -> bool {
+        self.user_readable() && self.writable()
+    }
+
+    // 判断 A 项是否为 1，即页面自上次清零后是否被访问过
+    pub fn accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
+
+    // 判断 D 项是否为 1，即页面自上次清零后是否被写入过
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
+
+    // 将 A 项清零
+    pub fn clear_accessed(&mut self) {
+        self.bits &= !(PTEFlags::A.bits() as usize);
+    }
+
+    // 将 D 项清零
+    #[allow(unused)]
+    pub fn clear_dirty(&mut self) {
+        self.bits &= !(PTEFlags::D.bits() as usize);
+    }
+
+    // R/W/X 中任意一位为 1 即为叶子页表项，否则指向下一级页表
+    pub fn is_leaf(&self) -> bool {
+        self.readable() || self.writable() || self.executable()
+    }
 }
 
 /// 页表结构体
@@ -104,15 +147,50 @@ impl PageTable {
     }
     /// 临时创建一个专门用来手动查页表的 PageTable，它仅有一个从传入的 satp token 中得到的多级页表根节点的物理页号，它的 frames 字段为空，也即不实际控制任何资源
     /// Temporarily used to get arguments from user space.
+    ///
+    /// 根节点的物理页号必须位于可分配的物理内存中，debug 模式下会检查，否则查页表时会把任意内存当作页表访问
     pub fn from_token(satp: usize) -> Self {
+        let root_ppn = PhysPageNum::from(satp & ((1usize << 44) - 1));
+        debug_assert!(
+            Self::is_valid_root(root_ppn),
+            "invalid page table root {:?} in satp {:#x}",
+            root_ppn,
+            satp
+        );
         Self {
-            root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
+            root_ppn,
+            frames: Vec::new(),
+        }
+    }
+
+    /// 与 from_token 相同，但根节点的物理页号不在 [ekernel, MEMORY_END) 中时返回 None
+    #[allow(unused)]
+    pub fn try_from_token(satp: usize) -> Option<Self> {
+        let root_ppn = PhysPageNum::from(satp & ((1usize << 44) - 1));
+        if !Self::is_valid_root(root_ppn) {
+            return None;
+        }
+        Some(Self {
+            root_ppn,
             frames: Vec::new(),
+        })
+    }
+
+    // 页表的所有节点都由页帧分配器分配，因此根节点一定位于内核镜像之后、物理内存结束之前
+    fn is_valid_root(root_ppn: PhysPageNum) -> bool {
+        extern "C" {
+            fn ekernel();
         }
+        let start = PhysAddr::from(ekernel as usize).ceil();
+        let end = PhysAddr::from(MEMORY_END).floor();
+        start <= root_ppn && root_ppn < end
     }
 
     /// 在多级页表找到一个虚拟页号对应的页表项的可变引用方便后续的读写，如果在遍历的过程中发现有节点尚未创建则会新建一个节点
     fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        if !vpn.is_canonical() {
+            return None;
+        }
         // 获取虚拟页号对应的三级页索引（每 9 位为一级页索引）
         let mut idxs = vpn.indexes();
         // 获得当前结点的物理页号，当前为当前结点的物理页号
@@ -134,33 +212,102 @@ impl PageTable {
                 break;
             }
             // 如果发现有页表项没有被创建（或无效），则新建一个页表项
+            // 物理页帧耗尽时返回 None
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
+            // 该虚拟页号已经被一个大页覆盖，不能再向下遍历
+            if pte.is_leaf() {
+                return None;
+            }
             ppn = pte.ppn();
         }
         result
     }
 
+    /// 与 find_pte_create 类似，但只遍历到第二级页表，返回可作为 2MiB 大页叶子的页表项
+    ///
+    /// vpn 不是合法的 SV39 虚拟页号、物理页帧耗尽或者已经被 1GiB 的叶子覆盖时返回 None
+    fn find_huge_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        if !vpn.is_canonical() {
+            return None;
+        }
+        let idxs = vpn.indexes();
+        let pte = &mut self.root_ppn.get_pte_array()[idxs[0]];
+        if !pte.is_valid() {
+            let frame = frame_alloc()?;
+            *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+            self.frames.push(frame);
+        }
+        if pte.is_leaf() {
+            return None;
+        }
+        Some(&mut pte.ppn().get_pte_array()[idxs[1]])
+    }
+
     /// find_pte 和之前的 find_pte_create 不同之处在于它不会试图分配物理页帧。一旦在多级页表上遍历遇到空指针它就会直接返回 None 表示无法正确找到传入的虚拟页号对应的页表项
+    /// 
+    /// 若在第三级之前遇到叶子页表项（大页），则直接返回该页表项
     pub fn find_pte(&self, vpn: VirtPageNum) -> Option<&PageTableEntry> {
+        self.find_leaf(vpn).map(|(pte, _)| pte)
+    }
+
+    /// 返回叶子页表项以及它所在的层级（0 为根节点）
+    fn find_leaf(&self, vpn: VirtPageNum) -> Option<(&PageTableEntry, usize)> {
+        self.locate_leaf(vpn)
+            .map(|(ppn, idx, level)| (&ppn.get_pte_array()[idx], level))
+    }
+
+    /// 与 find_leaf 相同，但返回叶子页表项的可变引用
+    fn find_leaf_mut(&mut self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, usize)> {
+        self.locate_leaf(vpn)
+            .map(|(ppn, idx, level)| (&mut ppn.get_pte_array()[idx], level))
+    }
+
+    /// 返回叶子页表项所在节点的物理页号、在节点中的下标以及节点所在的层级
+    ///
+    /// 不是合法的 SV39 虚拟页号时返回 None
+    fn locate_leaf(&self, vpn: VirtPageNum) -> Option<(PhysPageNum, usize, usize)> {
+        if !vpn.is_canonical() {
+            return None;
+        }
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &ppn.get_pte_array()[*idx];
-            if i == 2 {
-                result = Some(pte);
-                break;
+            if i == 2 || (pte.is_valid() && pte.is_leaf()) {
+                return Some((ppn, *idx, i));
             }
             if !pte.is_valid() {
                 return None;
             }
             ppn = pte.ppn();
         }
-        result
+        None
+    }
+
+    /// 与 find_pte 相同的遍历过程，但返回沿途经过的每一级页表项，便于调试缺页异常
+    ///
+    /// 遍历在遇到无效的非叶子页表项时停止，之后各级均为 None
+    #[allow(unused)]
+    pub fn walk(&self, vpn: VirtPageNum) -> [Option<PageTableEntry>; 3] {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        let mut path = [None; 3];
+        if !vpn.is_canonical() {
+            return path;
+        }
+        for (i, idx) in idxs.iter().enumerate() {
+            let pte = ppn.get_pte_array()[*idx];
+            path[i] = Some(pte);
+            if !pte.is_valid() || pte.is_leaf() {
+                break;
+            }
+            ppn = pte.ppn();
+        }
+        path
     }
 
     /// 操作系统动态维护一个虚拟页号到页表项的映射，支持插入/删除键值对
@@ -175,17 +322,124 @@ impl PageTable {
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
     }
 
+    /// 清除 vpn 对应叶子页表项的 A 位，返回清除之前该页面是否被访问过，页面未被映射时返回 None
+    #[allow(unused)]
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) -> Option<bool> {
+        let (pte, _) = self.find_leaf_mut(vpn)?;
+        if !pte.is_valid() {
+            return None;
+        }
+        let accessed = pte.accessed();
+        pte.clear_accessed();
+        // 快表中可能缓存了 A 位已经置位的表项，需要刷新才能让硬件重新置位
+        if self.is_active() {
+            flush_tlb(vpn);
+        }
+        Some(accessed)
+    }
+
+    /// 原地修改一个已经存在的叶子页表项的物理页号和标志位，供 mprotect 和写时复制等场景使用
+    #[allow(unused)]
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    /// 在第二级页表中插入一个 2MiB 大页的叶子页表项，vpn 和 ppn 都必须按大页对齐
+    pub fn map_huge(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pages = HUGE_PAGE_SIZE / PAGE_SIZE;
+        assert!(
+            vpn.0 % pages == 0 && ppn.0 % pages == 0,
+            "huge page {:?} -> {:?} is not aligned",
+            vpn,
+            ppn
+        );
+        let pte = self.find_huge_pte_create(vpn).unwrap();
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
+
+    /// 删除一个 2MiB 大页的叶子页表项
+    #[allow(unused)]
+    pub fn unmap_huge(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_huge_pte_create(vpn).unwrap();
+        assert!(
+            pte.is_valid() && pte.is_leaf(),
+            "vpn {:?} is not a huge page before unmapping",
+            vpn
+        );
+        *pte = PageTableEntry::empty();
+    }
+
+    /// 与 map 相同，但在分配中间节点时物理页帧耗尽则返回 Err 而不是 panic
+    pub fn try_map(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), ()> {
+        let pte = self.find_pte_create(vpn).ok_or(())?;
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
+    }
+
     #[allow(unused)]
     /// 相对的，我们通过 unmap 方法来删除一个键值对，在调用时仅需给出作为索引的虚拟页号即可
+    /// 
+    /// vpn 落在大页中时 panic，大页需要通过 unmap_huge 整体删除，否则会把大页的物理页帧当作下一级页表修改
+    ///
+    /// 清空叶子页表项之后，若某个中间节点已经不再含有任何有效的页表项，则将其所在的物理页帧回收并清空父节点中对应的页表项，自底向上直到根节点（根节点不回收）
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte_create(vpn).unwrap();
+        assert!(vpn.is_canonical(), "vpn {:?} is not a valid SV39 page", vpn);
+        let idxs = vpn.indexes();
+        // 记录遍历经过的各级节点所在的物理页号
+        let mut nodes = [self.root_ppn; 3];
+        for i in 0..2 {
+            let pte = nodes[i].get_pte_array()[idxs[i]];
+            assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+            assert!(!pte.is_leaf(), "vpn {:?} is inside a huge page", vpn);
+            nodes[i + 1] = pte.ppn();
+        }
+        let pte = &mut nodes[2].get_pte_array()[idxs[2]];
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+
+        for i in (1..3).rev() {
+            if nodes[i].get_pte_array().iter().any(|pte| pte.is_valid()) {
+                break;
+            }
+            // from_token 得到的页表不持有任何节点，不能回收
+            let pos = match self.frames.iter().position(|frame| frame.ppn == nodes[i]) {
+                Some(pos) => pos,
+                None => break,
+            };
+            nodes[i - 1].get_pte_array()[idxs[i - 1]] = PageTableEntry::empty();
+            self.frames.swap_remove(pos);
+        }
     }
 
     /// 调用 find_pte 来实现，如果能够找到页表项，那么它会将页表项拷贝一份并返回，否则就 返回一个 None
+    /// 
+    /// 若 vpn 落在大页中，返回的页表项中的物理页号为 vpn 在大页内实际对应的物理页号
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).copied()
+        self.find_leaf(vpn).map(|(pte, level)| match level {
+            2 => *pte,
+            _ => {
+                let offset = vpn.0 & ((1usize << (9 * (2 - level))) - 1);
+                PageTableEntry::new(PhysPageNum(pte.ppn().0 + offset), pte.flags())
+            }
+        })
+    }
+
+    /// 与 translate 相同，但 vpn 位于高半部分时直接返回 None，用于翻译来自用户程序的地址，
+    /// 避免用户程序传入高半部分的指针让系统调用读取跳板、Trap 上下文等不属于它的页面
+    pub fn translate_user(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        if !vpn.in_user_half() {
+            return None;
+        }
+        self.translate(vpn)
     }
 
     /// 地址空间高 256G 是用户空间，低 256G 是内核空间
@@ -194,8 +448,26 @@ impl PageTable {
     pub fn token(&self) -> usize {
         8usize << 60 | self.root_ppn.0
     }
+
+    /// 判断当前多级页表是否正在被 MMU 使用，即 satp 中的值与 token 相同
+    pub fn is_active(&self) -> bool {
+        satp::read().bits() == self.token()
+    }
+}
+
+/// 只刷新快表中虚拟页号 vpn 所在页面的表项，而不是像 sfence.vma 不带参数那样清空整个快表
+///
+/// 只有修改正在使用的页表时才需要调用。处理系统调用时 satp 中是内核地址空间，
+/// 用户地址空间的改动会在返回用户态时由 __restore 中的 sfence.vma 统一刷新，
+/// 因此被 munmap 的页面在下一次访问时一定会触发缺页异常
+pub fn flush_tlb(vpn: VirtPageNum) {
+    let va: VirtAddr = vpn.into();
+    unsafe {
+        core::arch::asm!("sfence.vma {}, x0", in(reg) va.0);
+    }
 }
 
+#[allow(unused)]
 /// translate a pointer to a mutable u8 Vec through page table
 /// 
 /// 同样由于内核和应用地址空间的隔离， sys_write 不再能够直接访问位于应用空间中的数据，而需要手动查页表才能知道那些 数据被放置在哪些物理页帧上并进行访问。
@@ -203,22 +475,544 @@ impl PageTable {
 /// 为此，页表模块 page_table 提供了将应用地址空间中一个缓冲区转化为在内核空间中能够直接访问的形式的辅助函数
 pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
     let page_table = PageTable::from_token(token);
+    extend_to_static(translated_byte_buffer_ref(&page_table, ptr, len).unwrap())
+}
+
+/// 与 translated_byte_buffer 相同，但返回的切片借用 page_table，在切片仍被使用时无法释放或修改页表
+///
+/// 切片的生命周期 'a 与 page_table 的借用绑定，例如在切片仍被使用时 drop(page_table) 会被借用检查拒绝。
+/// 缓冲区中任意一页未被映射或不允许用户态读取时返回 None，而不是让内核 panic
+#[allow(clippy::needless_lifetimes)]
+pub fn translated_byte_buffer_ref<'a>(
+    page_table: &'a PageTable,
+    ptr: *const u8,
+    len: usize,
+) -> Option<Vec<&'a mut [u8]>> {
+    translated_user_buffer_ref(page_table, ptr, len, false).ok()
+}
+
+/// 与 translated_byte_buffer 相同，但在缓冲区中任意一页未被映射或不允许用户态读取时返回 Err 而不是 panic，避免用户程序借助内核读取不属于它的内存
+pub fn translated_byte_buffer_checked(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+) -> Result<Vec<&'static mut [u8]>, ()> {
+    translated_user_buffer(token, ptr, len, false)
+}
+
+/// 与 translated_byte_buffer_checked 相同，但还要求缓冲区的每一页都允许用户态写入，用于内核向用户缓冲区写入数据
+pub fn translated_byte_buffer_writable(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+) -> Result<Vec<&'static mut [u8]>, ()> {
+    translated_user_buffer(token, ptr, len, true)
+}
+
+/// 将 value 按字节写入用户地址空间中 ptr 处，value 可以跨越页面边界
+///
+/// 任意一页未被映射或不允许用户态写入时返回 Err，此时不会写入任何数据
+pub fn copy_to_user<T: Copy>(token: usize, ptr: *mut T, value: &T) -> Result<(), ()> {
+    let len = core::mem::size_of::<T>();
+    let page_table = PageTable::from_token(token);
+    let buffers = translated_user_buffer_ref(&page_table, ptr as *const u8, len, true)?;
+    let bytes = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, len) };
+    let mut offset = 0;
+    for buffer in buffers {
+        buffer.copy_from_slice(&bytes[offset..offset + buffer.len()]);
+        offset += buffer.len();
+    }
+    Ok(())
+}
+
+/// 用户地址空间中的一个缓冲区，由 translated_byte_buffer 等函数得到的若干段按页切分的切片组成
+pub struct UserBuffer {
+    pub buffers: Vec<&'static mut [u8]>,
+}
+
+impl UserBuffer {
+    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
+        Self { buffers }
+    }
+
+    /// 按顺序遍历缓冲区中的每个字节，不受页面边界的影响
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.buffers
+            .iter()
+            .flat_map(|buffer| buffer.iter().copied())
+    }
+
+    /// 缓冲区的总字节数
+    pub fn len(&self) -> usize {
+        self.buffers.iter().map(|buffer| buffer.len()).sum()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 将 src 依次写入各段切片，超出缓冲区的部分被截断，返回实际写入的字节数
+    #[allow(unused)]
+    pub fn write(&mut self, src: &[u8]) -> usize {
+        let mut written = 0;
+        for buffer in self.buffers.iter_mut() {
+            if written == src.len() {
+                break;
+            }
+            let n = buffer.len().min(src.len() - written);
+            buffer[..n].copy_from_slice(&src[written..written + n]);
+            written += n;
+        }
+        written
+    }
+}
+
+/// 按顺序返回缓冲区 [ptr, ptr + len) 所在的每一页对应的物理页号，任意一页未被映射时返回 None
+#[allow(unused)]
+pub fn translated_ppn_range(token: usize, ptr: *const u8, len: usize) -> Option<Vec<PhysPageNum>> {
+    let page_table = PageTable::from_token(token);
+    let start = ptr as usize;
+    let end = start.checked_add(len)?;
+    if len == 0 {
+        return Some(Vec::new());
+    }
+    VPNRange::new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil())
+        .into_iter()
+        .map(|vpn| {
+            page_table
+                .translate(vpn)
+                .filter(|pte| pte.is_valid())
+                .map(|pte| pte.ppn())
+        })
+        .collect()
+}
+
+/// 返回用户地址空间中缓冲区 [ptr, ptr + len) 实际占用的各段物理内存 (起始物理地址, 字节数)，
+/// 物理上相邻的页面被合并为一段，可以直接交给 DMA 等按物理地址访问内存的设备
+///
+/// 任意一页未被映射、位于高半部分或者范围溢出时返回 None
+#[allow(unused)]
+pub fn translated_phys_spans(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+) -> Option<Vec<(PhysAddr, usize)>> {
+    let page_table = PageTable::from_token(token);
+    let mut start = ptr as usize;
+    let end = start.checked_add(len)?;
+    let mut spans: Vec<(PhysAddr, usize)> = Vec::new();
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let pte = page_table
+            .translate_user(start_va.floor())
+            .filter(|pte| pte.is_valid())?;
+        let pa = PhysAddr::from(PhysAddr::from(pte.ppn()).0 + start_va.page_offset());
+        let n = (PAGE_SIZE - start_va.page_offset()).min(end - start);
+        match spans.last_mut() {
+            Some((span_pa, span_len)) if span_pa.0 + *span_len == pa.0 => *span_len += n,
+            _ => spans.push((pa, n)),
+        }
+        start += n;
+    }
+    Some(spans)
+}
+
+/// 从用户地址空间中 ptr 处按字节读出一个 T，T 可以跨越页面边界
+///
+/// 任意一页未被映射或不允许用户态读取时返回 Err
+#[allow(unused)]
+pub fn copy_from_user<T: Copy>(token: usize, ptr: *const T) -> Result<T, ()> {
+    let len = core::mem::size_of::<T>();
+    let page_table = PageTable::from_token(token);
+    let buffers = translated_user_buffer_ref(&page_table, ptr as *const u8, len, false)?;
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, len) };
+    let mut offset = 0;
+    for buffer in buffers {
+        bytes[offset..offset + buffer.len()].copy_from_slice(buffer);
+        offset += buffer.len();
+    }
+    Ok(unsafe { value.assume_init() })
+}
+
+/// translated_str 最多读取的字节数（不含结尾的 \0），避免缺少结尾的字符串让内核一直扫描下去
+const MAX_STR_LEN: usize = PAGE_SIZE;
+
+/// 从用户地址空间中 ptr 处逐页读取一个以 \0 结尾的字符串，用于 exec、open 等以路径为参数的系统调用
+///
+/// 遇到未被映射或不允许用户态读取的页面、超过 MAX_STR_LEN 仍未遇到 \0 或者不是合法的 UTF-8 时返回 None
+#[allow(unused)]
+pub fn translated_str(token: usize, ptr: *const u8) -> Option<String> {
+    let page_table = PageTable::from_token(token);
+    let mut va = ptr as usize;
+    let mut bytes = Vec::new();
+    loop {
+        let start_va = VirtAddr::from(va);
+        let pte = page_table
+            .translate_user(start_va.floor())
+            .filter(|pte| pte.user_readable())?;
+        let page = &pte.ppn().get_bytes_array()[start_va.page_offset()..];
+        let nul = page.iter().position(|&byte| byte == 0);
+        bytes.extend_from_slice(&page[..nul.unwrap_or(page.len())]);
+        if bytes.len() > MAX_STR_LEN {
+            return None;
+        }
+        if nul.is_some() {
+            break;
+        }
+        va = va.checked_add(page.len())?;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn translated_user_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    writable: bool,
+) -> Result<Vec<&'static mut [u8]>, ()> {
+    let page_table = PageTable::from_token(token);
+    translated_user_buffer_ref(&page_table, ptr, len, writable).map(extend_to_static)
+}
+
+fn translated_user_buffer_ref(
+    page_table: &PageTable,
+    ptr: *const u8,
+    len: usize,
+    writable: bool,
+) -> Result<Vec<&mut [u8]>, ()> {
     let mut start = ptr as usize;
-    let end = start + len;
+    let end = start.checked_add(len).ok_or(())?;
     let mut v = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let pte = page_table.translate_user(vpn).ok_or(())?;
+        if !pte.user_readable() || (writable && !pte.user_writable()) {
+            return Err(());
+        }
+        let ppn = pte.ppn();
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
-        if end_va.page_offset() == 0 {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
-        } else {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
-        }
+        v.push(ppn.get_bytes_range(start_va.page_offset(), end_va.0 - start_va.0));
         start = end_va.into();
     }
-    v
+    Ok(v)
+}
+
+// 切片实际指向物理页帧，与 from_token 得到的临时页表无关，这里只是去掉对临时页表的借用；
+// 调用者需要自行保证在使用切片期间对应的页面没有被 munmap
+fn extend_to_static(buffers: Vec<&mut [u8]>) -> Vec<&'static mut [u8]> {
+    buffers
+        .into_iter()
+        .map(|buffer| unsafe { core::slice::from_raw_parts_mut(buffer.as_mut_ptr(), buffer.len()) })
+        .collect()
+}
+
+#[allow(unused)]
+/// a simple test for page table walking
+pub fn page_table_test() {
+    let mut page_table = PageTable::new();
+    let frame = frame_alloc().unwrap();
+    let vpn = VirtPageNum(0x10);
+    page_table.map(vpn, frame.ppn, PTEFlags::R | PTEFlags::W);
+    assert!(page_table.walk(vpn).iter().all(|pte| pte.map_or(false, |pte| pte.is_valid())));
+    let path = page_table.walk(VirtPageNum(0x1 << 18));
+    assert!(!path[0].unwrap().is_valid());
+    assert!(path[1].is_none() && path[2].is_none());
+    page_table.unmap(vpn);
+    assert_eq!(page_table.frames.len(), 1);
+    // 设置了 RSW 位的页表项同样可以正常取出标志位
+    let pte = PageTableEntry::new(frame.ppn, PTEFlags::V | PTEFlags::R | PTEFlags::RSW1);
+    assert_eq!(pte.flags(), PTEFlags::V | PTEFlags::R | PTEFlags::RSW1);
+    assert_eq!(pte.ppn(), frame.ppn);
+    // A 位的读取和清零
+    let mut pte = PageTableEntry::new(frame.ppn, PTEFlags::V | PTEFlags::R | PTEFlags::A);
+    assert!(pte.accessed() && !pte.dirty());
+    pte.clear_accessed();
+    assert!(!pte.accessed());
+    assert_eq!(pte.flags(), PTEFlags::V | PTEFlags::R);
+    info!("page_table_test passed!");
+}
+
+pub fn huge_page_test() {
+    let mut page_table = PageTable::new();
+    let pages = HUGE_PAGE_SIZE / PAGE_SIZE;
+    // 只写页表项而不访问页帧的内容，因此可以直接映射到按大页对齐的物理页号
+    let (vpn, ppn) = (VirtPageNum(pages), PhysPageNum(0x80000 + pages));
+    page_table.map_huge(vpn, ppn, PTEFlags::R | PTEFlags::W);
+    let pte = page_table.translate(VirtPageNum(vpn.0 + 5)).unwrap();
+    assert_eq!(pte.ppn(), PhysPageNum(ppn.0 + 5));
+    assert!(page_table.walk(vpn)[2].is_none());
+    // 不合法的 SV39 虚拟页号不会被遍历
+    assert!(page_table
+        .find_huge_pte_create(VirtPageNum(1usize << 27))
+        .is_none());
+    page_table.unmap_huge(vpn);
+    assert!(page_table.translate(vpn).is_none());
+    info!("huge_page_test passed!");
+}
+
+pub fn copy_to_user_test() {
+    let mut page_table = PageTable::new();
+    let frames = [frame_alloc().unwrap(), frame_alloc().unwrap()];
+    let flags = PTEFlags::R | PTEFlags::W | PTEFlags::U;
+    page_table.map(VirtPageNum(0x10), frames[0].ppn, flags);
+    page_table.map(VirtPageNum(0x11), frames[1].ppn, flags);
+    let token = page_table.token();
+    // 前 8 个字节位于第一页的末尾，后 8 个字节位于第二页的开头
+    let ptr = (0x11 * PAGE_SIZE - 8) as *mut [u64; 2];
+    let value = [0x0123_4567_89ab_cdefu64, 0xfedc_ba98_7654_3210];
+    assert_eq!(copy_to_user(token, ptr, &value), Ok(()));
+    let first = &frames[0].ppn.get_bytes_array()[PAGE_SIZE - 8..];
+    let second = &frames[1].ppn.get_bytes_array()[..8];
+    assert_eq!(first, &value[0].to_ne_bytes());
+    assert_eq!(second, &value[1].to_ne_bytes());
+    // 第三页未被映射
+    let ptr = (0x12 * PAGE_SIZE - 8) as *mut [u64; 2];
+    assert_eq!(copy_to_user(token, ptr, &value), Err(()));
+    info!("copy_to_user_test passed!");
+}
+
+pub fn copy_from_user_test() {
+    let mut page_table = PageTable::new();
+    let frames = [
+        frame_alloc().unwrap(),
+        frame_alloc().unwrap(),
+        frame_alloc().unwrap(),
+    ];
+    page_table.map(VirtPageNum(0x10), frames[0].ppn, PTEFlags::R | PTEFlags::U);
+    page_table.map(VirtPageNum(0x11), frames[1].ppn, PTEFlags::R | PTEFlags::U);
+    // 内核页面不允许用户态读取
+    page_table.map(VirtPageNum(0x20), frames[2].ppn, PTEFlags::R);
+    let token = page_table.token();
+    let value = [0x0123_4567_89ab_cdefu64, 0xfedc_ba98_7654_3210];
+    frames[0].ppn.get_bytes_array()[PAGE_SIZE - 8..].copy_from_slice(&value[0].to_ne_bytes());
+    frames[1].ppn.get_bytes_array()[..8].copy_from_slice(&value[1].to_ne_bytes());
+    let ptr = (0x11 * PAGE_SIZE - 8) as *const [u64; 2];
+    assert_eq!(copy_from_user(token, ptr), Ok(value));
+    // 第三页未被映射
+    let ptr = (0x12 * PAGE_SIZE - 8) as *const [u64; 2];
+    assert_eq!(copy_from_user(token, ptr), Err(()));
+    let ptr = (0x20 * PAGE_SIZE) as *const u64;
+    assert_eq!(copy_from_user(token, ptr), Err(()));
+    info!("copy_from_user_test passed!");
+}
+
+pub fn translated_str_test() {
+    let mut page_table = PageTable::new();
+    let frames = [frame_alloc().unwrap(), frame_alloc().unwrap()];
+    page_table.map(VirtPageNum(0x10), frames[0].ppn, PTEFlags::R | PTEFlags::U);
+    page_table.map(VirtPageNum(0x11), frames[1].ppn, PTEFlags::R | PTEFlags::U);
+    let token = page_table.token();
+    // 完全位于一页之内
+    frames[0].ppn.get_bytes_array()[..6].copy_from_slice(b"hello\0");
+    let ptr = (0x10 * PAGE_SIZE) as *const u8;
+    assert_eq!(translated_str(token, ptr).as_deref(), Some("hello"));
+    // 跨越两页
+    frames[0].ppn.get_bytes_array()[PAGE_SIZE - 3..].copy_from_slice(b"/bi");
+    frames[1].ppn.get_bytes_array()[..3].copy_from_slice(b"n\0\0");
+    let ptr = (0x11 * PAGE_SIZE - 3) as *const u8;
+    assert_eq!(translated_str(token, ptr).as_deref(), Some("/bin"));
+    // 两页中都没有 \0，在读到未映射的第三页之前就超出了长度上限
+    for frame in frames.iter() {
+        frame.ppn.get_bytes_array().fill(b'a');
+    }
+    let ptr = (0x10 * PAGE_SIZE) as *const u8;
+    assert_eq!(translated_str(token, ptr), None);
+    info!("translated_str_test passed!");
+}
+
+pub fn canonical_test() {
+    let mut page_table = PageTable::new();
+    let frame = frame_alloc().unwrap();
+    let vpn = VirtPageNum(0x10);
+    page_table.map(vpn, frame.ppn, PTEFlags::R | PTEFlags::U);
+    assert!(page_table.translate(vpn).is_some());
+    // 超出 27 位的页号的低 27 位与 vpn 相同，但不能被当作 vpn 翻译
+    let aliased = VirtPageNum(vpn.0 | (1 << 27));
+    assert!(!aliased.is_canonical());
+    assert!(page_table.translate(aliased).is_none());
+    assert!(page_table.walk(aliased).iter().all(|pte| pte.is_none()));
+    assert!(page_table.try_map(aliased, frame.ppn, PTEFlags::R).is_err());
+    // 高半部分的地址必须是第 38 位的符号扩展
+    let non_canonical = VirtAddr::from(0xffff_ff00_0000_0000 | (vpn.0 << 12));
+    assert!(!non_canonical.is_canonical());
+    assert!(page_table.translate(non_canonical.floor()).is_none());
+    let ptr = non_canonical.0 as *const u8;
+    assert!(translated_byte_buffer_checked(page_table.token(), ptr, 1).is_err());
+    // 跳板所在的最高页面是合法的
+    assert!(VirtAddr::from(usize::MAX).is_canonical());
+    assert!(VirtAddr::from(usize::MAX).floor().is_canonical());
+    info!("canonical_test passed!");
+}
+
+pub fn translated_ppn_range_test() {
+    let mut page_table = PageTable::new();
+    let frames = [
+        frame_alloc().unwrap(),
+        frame_alloc().unwrap(),
+        frame_alloc().unwrap(),
+    ];
+    for (i, frame) in frames.iter().enumerate() {
+        page_table.map(VirtPageNum(0x10 + i), frame.ppn, PTEFlags::R | PTEFlags::U);
+    }
+    let token = page_table.token();
+    // 缓冲区从第一页中间开始，到第三页中间结束
+    let ptr = (0x10 * PAGE_SIZE + 0x800) as *const u8;
+    let ppns = translated_ppn_range(token, ptr, 2 * PAGE_SIZE).unwrap();
+    assert_eq!(ppns.len(), 3);
+    for (i, ppn) in ppns.iter().enumerate() {
+        assert_eq!(*ppn, frames[i].ppn);
+        assert_eq!(
+            *ppn,
+            page_table.translate(VirtPageNum(0x10 + i)).unwrap().ppn()
+        );
+    }
+    assert_eq!(translated_ppn_range(token, ptr, 0).unwrap().len(), 0);
+    // 第四页未被映射
+    assert!(translated_ppn_range(token, ptr, 3 * PAGE_SIZE).is_none());
+    info!("translated_ppn_range_test passed!");
+}
+
+pub fn user_buffer_test() {
+    let mut page_table = PageTable::new();
+    let frames = [frame_alloc().unwrap(), frame_alloc().unwrap()];
+    page_table.map(VirtPageNum(0x10), frames[0].ppn, PTEFlags::R | PTEFlags::U);
+    page_table.map(VirtPageNum(0x11), frames[1].ppn, PTEFlags::R | PTEFlags::U);
+    // "é" 的 UTF-8 编码为两个字节，恰好被页面边界分开
+    let text = "caf\u{e9}!";
+    let bytes = text.as_bytes();
+    frames[0].ppn.get_bytes_array()[PAGE_SIZE - 4..].copy_from_slice(&bytes[..4]);
+    frames[1].ppn.get_bytes_array()[..2].copy_from_slice(&bytes[4..]);
+    let ptr = (0x11 * PAGE_SIZE - 4) as *const u8;
+    let buffers = translated_byte_buffer_checked(page_table.token(), ptr, bytes.len()).unwrap();
+    let user_buffer = UserBuffer::new(buffers);
+    assert_eq!(user_buffer.buffers.len(), 2);
+    // 逐页解码会失败，拼接之后才是合法的 UTF-8
+    assert!(core::str::from_utf8(user_buffer.buffers[0]).is_err());
+    let collected: Vec<u8> = user_buffer.bytes().collect();
+    assert_eq!(core::str::from_utf8(&collected), Ok(text));
+    info!("user_buffer_test passed!");
+}
+
+pub fn user_buffer_write_test() {
+    let mut page_table = PageTable::new();
+    let frames = [frame_alloc().unwrap(), frame_alloc().unwrap()];
+    let flags = PTEFlags::R | PTEFlags::W | PTEFlags::U;
+    page_table.map(VirtPageNum(0x10), frames[0].ppn, flags);
+    page_table.map(VirtPageNum(0x11), frames[1].ppn, flags);
+    let token = page_table.token();
+    // 缓冲区的前 4 个字节在第一页，后 4 个字节在第二页
+    let ptr = (0x11 * PAGE_SIZE - 4) as *const u8;
+    let new_buffer = || UserBuffer::new(translated_byte_buffer_writable(token, ptr, 8).unwrap());
+    assert_eq!(new_buffer().len(), 8);
+
+    let mut user_buffer = new_buffer();
+    assert_eq!(user_buffer.write(b"abc"), 3);
+    assert!(user_buffer.bytes().take(3).eq(b"abc".iter().copied()));
+
+    let mut user_buffer = new_buffer();
+    assert_eq!(user_buffer.write(b"01234567"), 8);
+    assert_eq!(&frames[0].ppn.get_bytes_array()[PAGE_SIZE - 4..], b"0123");
+    assert_eq!(&frames[1].ppn.get_bytes_array()[..4], b"4567");
+
+    let mut user_buffer = new_buffer();
+    assert_eq!(user_buffer.write(b"ABCDEFGHIJ"), 8);
+    assert!(user_buffer.bytes().eq(b"ABCDEFGH".iter().copied()));
+    // 超出缓冲区的部分没有被写入
+    assert_eq!(frames[1].ppn.get_bytes_array()[4], 0);
+    info!("user_buffer_write_test passed!");
+}
+
+pub fn from_token_test() {
+    let page_table = PageTable::new();
+    let token = page_table.token();
+    let checked = PageTable::try_from_token(token).unwrap();
+    assert_eq!(checked.root_ppn, page_table.root_ppn);
+    // 根节点落在内核镜像中或者物理内存之外
+    let mode = 8usize << 60;
+    assert!(PageTable::try_from_token(mode).is_none());
+    let end = PhysAddr::from(MEMORY_END).floor();
+    assert!(PageTable::try_from_token(mode | end.0).is_none());
+    assert!(PageTable::try_from_token(mode | ((1usize << 44) - 1)).is_none());
+    info!("from_token_test passed!");
+}
+
+pub fn translate_user_test() {
+    let mut page_table = PageTable::new();
+    let frames = [frame_alloc().unwrap(), frame_alloc().unwrap()];
+    let flags = PTEFlags::R | PTEFlags::W | PTEFlags::U;
+    // 低半部分的最后一页和高半部分的第一页
+    let user = VirtAddr::from((1usize << 38) - PAGE_SIZE).floor();
+    let kernel = VirtAddr::from(usize::MAX << 38).floor();
+    page_table.map(user, frames[0].ppn, flags);
+    page_table.map(kernel, frames[1].ppn, flags);
+    assert_eq!(
+        page_table.translate_user(user).unwrap().ppn(),
+        frames[0].ppn
+    );
+    assert!(page_table.translate(kernel).is_some());
+    assert!(page_table.translate_user(kernel).is_none());
+    // 即使页表项带有 U 位，也不能通过高半部分的指针访问
+    let ptr = VirtAddr::from(kernel).0 as *const u8;
+    assert!(translated_byte_buffer_checked(page_table.token(), ptr, 1).is_err());
+    info!("translate_user_test passed!");
+}
+
+pub fn translated_byte_buffer_ref_test() {
+    let mut page_table = PageTable::new();
+    let frames = [frame_alloc().unwrap(), frame_alloc().unwrap()];
+    page_table.map(VirtPageNum(0x10), frames[0].ppn, PTEFlags::R | PTEFlags::U);
+    page_table.map(VirtPageNum(0x11), frames[1].ppn, PTEFlags::R | PTEFlags::W);
+    frames[0].ppn.get_bytes_array()[PAGE_SIZE - 4..].copy_from_slice(b"page");
+    // 切片借用 page_table，使用完之后才能修改页表
+    let ptr = (0x11 * PAGE_SIZE - 4) as *const u8;
+    let buffers = translated_byte_buffer_ref(&page_table, ptr, 4).unwrap();
+    assert_eq!(buffers.len(), 1);
+    assert_eq!(buffers[0], b"page");
+    drop(buffers);
+    // 不允许用户态读取的页面和未被映射的页面都返回 None
+    assert!(translated_byte_buffer_ref(&page_table, ptr, 8).is_none());
+    let ptr = (0x12 * PAGE_SIZE) as *const u8;
+    assert!(translated_byte_buffer_ref(&page_table, ptr, 1).is_none());
+    // 长度溢出时同样返回 None
+    assert!(translated_byte_buffer_ref(&page_table, ptr, usize::MAX).is_none());
+    page_table.unmap(VirtPageNum(0x10));
+    info!("translated_byte_buffer_ref_test passed!");
+}
+
+pub fn translated_phys_spans_test() {
+    // 只检查地址转换，不访问页帧的内容，因此可以直接映射到指定的物理页号
+    let mut page_table = PageTable::new();
+    let flags = PTEFlags::R | PTEFlags::U;
+    let ppns = [0x80400, 0x80401, 0x80500, 0x80600, 0x805ff];
+    for (i, &ppn) in ppns.iter().enumerate() {
+        page_table.map(VirtPageNum(0x10 + i), PhysPageNum(ppn), flags);
+    }
+    let token = page_table.token();
+    let base = 0x10 * PAGE_SIZE;
+    // 前两页在物理上相邻，合并为一段
+    let ptr = (base + 0x800) as *const u8;
+    let spans = translated_phys_spans(token, ptr, PAGE_SIZE).unwrap();
+    assert_eq!(spans, [(PhysAddr::from(0x80400800), PAGE_SIZE)]);
+    // 跨越三段不相邻的物理内存，物理页号递减的两页也不能合并
+    let ptr = (base + PAGE_SIZE + 0x800) as *const u8;
+    let spans = translated_phys_spans(token, ptr, 3 * PAGE_SIZE).unwrap();
+    assert_eq!(
+        spans,
+        [
+            (PhysAddr::from(0x80401800), 0x800),
+            (PhysAddr::from(0x80500000), PAGE_SIZE),
+            (PhysAddr::from(0x80600000), PAGE_SIZE),
+            (PhysAddr::from(0x805ff000), 0x800),
+        ]
+    );
+    assert_eq!(translated_phys_spans(token, ptr, 0), Some(Vec::new()));
+    // 范围中有未映射的页面
+    assert!(translated_phys_spans(token, ptr, 5 * PAGE_SIZE).is_none());
+    assert!(translated_phys_spans(token, ptr, usize::MAX).is_none());
+    info!("translated_phys_spans_test passed!");
 }
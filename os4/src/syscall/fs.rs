@@ -1,22 +1,60 @@
 //! File and filesystem-related syscalls
 
-use crate::mm::translated_byte_buffer;
-use crate::task::current_user_token;
+use crate::mm::{translated_byte_buffer_checked, translated_byte_buffer_writable, UserBuffer};
+use crate::sbi::console_getchar;
+use crate::task::{current_user_token, suspend_current_and_run_next};
+use alloc::vec::Vec;
 
+const FD_STDIN: usize = 0;
 const FD_STDOUT: usize = 1;
+const FD_STDERR: usize = 2;
 
-/// 尝试将每个字节数组切片转化为字符串 &str 然后输出
-pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
+/// 从 SBI 控制台逐字节读取输入，在读到至少一个字节之前让出 CPU，返回读取的字节数
+pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     match fd {
-        FD_STDOUT => {
-            let buffers = translated_byte_buffer(current_user_token(), buf, len);
-            for buffer in buffers {
-                print!("{}", core::str::from_utf8(buffer).unwrap());
+        FD_STDIN => {
+            let buffers = match translated_byte_buffer_writable(current_user_token(), buf, len) {
+                Ok(buffers) => buffers,
+                Err(_) => return -1,
+            };
+            let mut read = 0;
+            for byte in buffers.into_iter().flatten() {
+                let mut c = console_getchar();
+                // 没有可读的字符时 SBI 返回 0 或 -1
+                while c == 0 || c == usize::MAX {
+                    if read > 0 {
+                        return read as isize;
+                    }
+                    suspend_current_and_run_next();
+                    c = console_getchar();
+                }
+                *byte = c as u8;
+                read += 1;
             }
-            len as isize
+            read as isize
         }
-        _ => {
-            panic!("Unsupported fd in sys_write!");
+        // 不支持的文件描述符返回 -1，而不是让内核 panic
+        _ => -1,
+    }
+}
+
+/// 将各页中的字节拼接成连续的缓冲区后再转化为字符串 &str 输出，避免跨页的多字节字符被截断
+pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
+    match fd {
+        FD_STDOUT | FD_STDERR => {
+            let buffers = match translated_byte_buffer_checked(current_user_token(), buf, len) {
+                Ok(buffers) => buffers,
+                Err(_) => return -1,
+            };
+            let bytes: Vec<u8> = UserBuffer::new(buffers).bytes().collect();
+            let s = match core::str::from_utf8(&bytes) {
+                Ok(s) => s,
+                Err(_) => return -1,
+            };
+            print!("{}", s);
+            len as isize
         }
+        // 不支持的文件描述符返回 -1，而不是让内核 panic
+        _ => -1,
     }
 }
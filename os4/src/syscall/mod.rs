@@ -10,14 +10,28 @@
 //! `sys_` then the name of the syscall. You can find functions like this in
 //! submodules, and you should also implement syscalls this way.
 
+const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_BRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MADVISE: usize = 233;
 const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_GETPID: usize = 172;
 const SYSCALL_TASK_INFO: usize = 410;
+const SYSCALL_RESET_SYSCALL_TIMES: usize = 411;
+const SYSCALL_GET_TIME_MS: usize = 412;
+const SYSCALL_LAST_FAULT: usize = 413;
+const SYSCALL_SBRK: usize = 414;
+const SYSCALL_QUERY_PAGE: usize = 415;
+const SYSCALL_GET_BRK: usize = 416;
+const SYSCALL_MMAP_BATCH: usize = 417;
+const SYSCALL_MMAP_HINT: usize = 418;
 
 mod fs;
 mod process;
@@ -29,17 +43,39 @@ use crate::task::add_syscall_times;
 
 /// handle syscall exception with `syscall_id` and other arguments
 pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
-    // LAB1: You may need to update syscall info here.
+    // 在分发之前统一计数，保证每个系统调用恰好被统计一次，即使处理函数在参数检查阶段就返回 -1
     add_syscall_times(syscall_id);
     match syscall_id {
+        SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
         SYSCALL_YIELD => sys_yield(),
+        SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
         SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
+        SYSCALL_BRK => sys_brk(args[0] as isize),
         SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYSCALL_MADVISE => sys_madvise(args[0], args[1], args[2]),
         SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
+        SYSCALL_GETPID => sys_getpid(),
         SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
+        SYSCALL_RESET_SYSCALL_TIMES => sys_reset_syscall_times(),
+        SYSCALL_GET_TIME_MS => sys_get_time_ms(),
+        SYSCALL_LAST_FAULT => sys_last_fault(args[0] as *mut FaultInfo),
+        SYSCALL_SBRK => sys_sbrk(args[0] as isize),
+        SYSCALL_QUERY_PAGE => sys_query_page(args[0]),
+        SYSCALL_GET_BRK => sys_get_brk(),
+        SYSCALL_MMAP_BATCH => sys_mmap_batch(args[0] as *const MmapSpec, args[1]),
+        SYSCALL_MMAP_HINT => sys_mmap_hint(args[0], args[1], args[2]),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     }
 }
+
+/// 依次运行系统调用相关的测试
+pub fn run_tests() {
+    process::clock_gettime_test();
+    process::query_page_test();
+    process::getpid_test();
+    process::mmap_errno_test();
+}
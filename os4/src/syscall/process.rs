@@ -2,26 +2,55 @@
 
 use riscv::register::satp::{self};
 
-use crate::config::{MAX_SYSCALL_NUM, PAGE_SIZE};
-use crate::mm::{PageTable, PhysAddr, VirtAddr};
+use crate::config::{MAX_SYSCALL_NUM, PAGE_SIZE, TRAP_CONTEXT};
+use crate::mm::{
+    copy_from_user, copy_to_user, MapPermission, MemorySet, MmapError, PTEFlags, PageTable,
+    VirtAddr,
+};
 use crate::task::{
-    exit_current_and_run_next, get_start_time, get_syscall_times, get_task_status,
-    suspend_current_and_run_next, TaskStatus, mmap, munmap, current_user_token,
+    change_program_break, current_pid, current_user_token, exit_current_and_run_next,
+    get_last_fault, get_page_faults, get_peak_frames, get_program_break, get_resident_frames,
+    get_start_time, get_syscall_times, get_task_status, madvise_dontneed, mmap, mmap_batch,
+    mmap_hint, mprotect, munmap, reset_syscall_times, set_program_break,
+    suspend_current_and_run_next, TaskStatus,
 };
-use crate::timer::get_time_us;
+use crate::timer::{get_time_ns, get_time_us};
+use alloc::vec::Vec;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct TimeVal {
     pub sec: usize,
     pub usec: usize,
 }
 
+/// 纳秒精度的时间，对应 Linux 的 struct timespec
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+/// 单调时钟，从开机开始计时且不会被修改
+const CLOCK_MONOTONIC: usize = 1;
+
 #[derive(Clone, Copy)]
 pub struct TaskInfo {
     pub status: TaskStatus,
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
     pub time: usize,
+    pub peak_frames: usize,
+    pub resident_frames: usize,
+    pub page_faults: usize,
+}
+
+/// 最近一次缺页异常的地址和原因，没有发生过缺页异常时均为 0
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FaultInfo {
+    pub addr: usize,
+    pub cause: usize,
 }
 
 pub fn sys_exit(exit_code: i32) -> ! {
@@ -30,6 +59,11 @@ pub fn sys_exit(exit_code: i32) -> ! {
     panic!("Unreachable in sys_exit!");
 }
 
+/// 返回当前任务的 pid
+pub fn sys_getpid() -> isize {
+    current_pid() as isize
+}
+
 /// current task gives up resources for other tasks
 pub fn sys_yield() -> isize {
     suspend_current_and_run_next();
@@ -39,35 +73,74 @@ pub fn sys_yield() -> isize {
 // YOUR JOB: 引入虚地址后重写 sys_get_time
 pub fn sys_get_time(_ts: *mut TimeVal, _tz: usize) -> isize {
     let _us = get_time_us();
-    let ts = translate_from_virtual_address(_ts as usize) as *mut TimeVal;
-    unsafe {
-        *ts = TimeVal {
-            sec: _us / 1_000_000,
-            usec: _us % 1_000_000,
-        };
+    let ts = TimeVal {
+        sec: _us / 1_000_000,
+        usec: _us % 1_000_000,
+    };
+    match copy_to_user(current_user_token(), _ts, &ts) {
+        Ok(()) => 0,
+        Err(()) => -1,
+    }
+}
+
+/// 读取 clk_id 对应的时钟并以 TimeSpec 的形式写入用户缓冲区 ts，目前只支持 CLOCK_MONOTONIC
+///
+/// 时钟不受支持或者 ts 不可写时返回 -1
+pub fn sys_clock_gettime(clk_id: usize, ts: *mut TimeSpec) -> isize {
+    if clk_id != CLOCK_MONOTONIC {
+        return -1;
+    }
+    match copy_to_user(current_user_token(), ts, &monotonic_time()) {
+        Ok(()) => 0,
+        Err(()) => -1,
     }
-    0
+}
+
+// 读取单调时钟
+fn monotonic_time() -> TimeSpec {
+    let ns = get_time_ns();
+    TimeSpec {
+        sec: ns / 1_000_000_000,
+        nsec: ns % 1_000_000_000,
+    }
+}
+
+/// 直接以返回值的形式返回开机以来的毫秒数，不需要向用户缓冲区写入数据
+pub fn sys_get_time_ms() -> isize {
+    (get_time_us() / 1000) as isize
 }
 
 // YOUR JOB: 引入虚地址后重写 sys_task_info
 pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
-    let _ti = translate_from_virtual_address(ti as usize) as *mut TaskInfo;
-    unsafe {
-        *_ti = TaskInfo {
-            status: get_task_status(),
-            syscall_times: get_syscall_times(),
-            time: (get_time_us() - get_start_time()) / 1000,
-        }
+    let task_info = TaskInfo {
+        status: get_task_status(),
+        syscall_times: get_syscall_times(),
+        time: (get_time_us() - get_start_time()) / 1000,
+        peak_frames: get_peak_frames(),
+        resident_frames: get_resident_frames(),
+        page_faults: get_page_faults(),
+    };
+    match copy_to_user(current_user_token(), ti, &task_info) {
+        Ok(()) => 0,
+        Err(()) => -1,
     }
+}
+
+/// 清零当前任务的系统调用计数，便于按时间段采样
+///
+/// 本次调用在进入时已经被计数，清零后同样不再保留，因此下一次采样只包含之后发起的系统调用
+pub fn sys_reset_syscall_times() -> isize {
+    reset_syscall_times();
     0
 }
 
-/// 根据传入的虚拟地址转化为物理地址
-pub fn translate_from_virtual_address(vir_addr: usize) -> usize {
-    let page_table = PageTable::from_token(current_user_token());
-    let virtual_addr = VirtAddr::from(vir_addr);
-    let ppn = page_table.find_pte(virtual_addr.floor()).unwrap().ppn();
-    PhysAddr::from(ppn).0 + virtual_addr.page_offset()
+/// 获取当前任务最近一次缺页异常的地址和原因
+pub fn sys_last_fault(fault: *mut FaultInfo) -> isize {
+    let (addr, cause) = get_last_fault();
+    match copy_to_user(current_user_token(), fault, &FaultInfo { addr, cause }) {
+        Ok(()) => 0,
+        Err(()) => -1,
+    }
 }
 
 // CLUE: 从 ch4 开始不再对调度算法进行测试~
@@ -76,24 +149,224 @@ pub fn sys_set_priority(_prio: isize) -> isize {
 }
 
 // YOUR JOB: 扩展内核以实现 sys_mmap 和 sys_munmap
+// 评测要求 sys_mmap 和 sys_munmap 在任何错误时都返回 -1，需要区分错误原因时使用 sys_mmap_hint
 pub fn sys_mmap(_start: usize, _len: usize, _port: usize) -> isize {
-    // _start 要按页对齐
-    if _start & (PAGE_SIZE - 1) != 0 {
+    match mmap(_start, _len, _port) {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// 与 sys_mmap 相同，但 start 只是建议的地址：与已有映射重叠时向上寻找第一段足够大的空闲区域
+///
+/// 成功时返回实际映射的起始地址，失败时返回负的 errno
+pub fn sys_mmap_hint(start: usize, len: usize, port: usize) -> isize {
+    match mmap_hint(start, len, port) {
+        Ok(start) => start as isize,
+        Err(err) => mmap_errno(err),
+    }
+}
+
+/// sys_mmap_batch 中的一项，含义与 sys_mmap 的三个参数相同
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MmapSpec {
+    pub start: usize,
+    pub len: usize,
+    pub port: usize,
+}
+
+/// 一次 sys_mmap_batch 最多映射的区域数，限制内核为读取参数分配的内存
+const MAX_MMAP_BATCH: usize = 64;
+
+/// 依次映射 specs 指向的 count 个区域，全部成功时返回 0
+///
+/// 任意一个区域映射失败、count 超过 MAX_MMAP_BATCH 或者 specs 不可读时返回 -1，此时地址空间与调用之前相同
+pub fn sys_mmap_batch(specs: *const MmapSpec, count: usize) -> isize {
+    if count > MAX_MMAP_BATCH {
+        return -1;
+    }
+    let token = current_user_token();
+    let mut batch = Vec::with_capacity(count);
+    for i in 0..count {
+        match copy_from_user(token, specs.wrapping_add(i)) {
+            Ok(spec) => batch.push((spec.start, spec.len, spec.port)),
+            Err(()) => return -1,
+        }
+    }
+    match mmap_batch(&batch) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// madvise 的 advice 参数，表示应用不再需要这些页面的内容
+const MADV_DONTNEED: usize = 4;
+
+/// 目前只支持 MADV_DONTNEED：回收 [start, start + len) 中页面的物理页帧但保留映射，之后访问时读到的是全零的页面
+///
+/// 范围内有未映射的页面、包含跳板或 Trap 上下文等只属于内核的页面，或者 advice 不受支持时返回 -1
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    if advice != MADV_DONTNEED {
         return -1;
     }
-    
-    // _port 其余位必须为 0 且 0-2 位至少有一个为 1
-    if _port & 0x7 == 0 || _port & !0x7 != 0 {
+    match madvise_dontneed(start, len) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// 修改 [start, start + len) 中页面的访问权限，port 为 0 时表示不允许任何访问，之后访问这些页面的应用会被杀死
+///
+/// start 未按页对齐、port 含有 0-2 位以外的位、范围内有未映射的页面或者包含跳板和 Trap 上下文等只属于内核的页面时返回 -1
+pub fn sys_mprotect(start: usize, len: usize, port: usize) -> isize {
+    match mprotect(start, len, port) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// 查询当前任务地址空间中 addr 所在页面的页表项标志位，只保留 V/R/W/X/U 五位
+///
+/// 页面未映射或者不允许用户态访问（如跳板和 Trap 上下文）时返回 -1，不向应用泄露内核页面的信息
+pub fn sys_query_page(addr: usize) -> isize {
+    query_page(current_user_token(), addr)
+}
+
+// 在 token 对应的页表中查询 addr 所在页面的标志位
+fn query_page(token: usize, addr: usize) -> isize {
+    let page_table = PageTable::from_token(token);
+    let mask = PTEFlags::V | PTEFlags::R | PTEFlags::W | PTEFlags::X | PTEFlags::U;
+    match page_table.translate_user(VirtAddr::from(addr).floor()) {
+        Some(pte) if pte.is_valid() && pte.flags().contains(PTEFlags::U) => {
+            (pte.flags() & mask).bits() as isize
+        }
+        _ => -1,
+    }
+}
+
+/// 将 MmapError 转换为 Linux 中对应的负的 errno，只用于 sys_mmap_hint
+///
+/// 与 Linux 的 mmap 一致，参数不合法的几种错误都对应 EINVAL，物理页帧耗尽和超过地址空间上限都对应 ENOMEM，
+/// 因此不同的 MmapError 可能对应同一个 errno。评测要求 sys_mmap 和 sys_munmap 在任何错误时都返回 -1，
+/// 所以它们不使用这里的转换
+fn mmap_errno(err: MmapError) -> isize {
+    match err {
+        MmapError::Unaligned => -22,     // EINVAL
+        MmapError::InvalidPort => -22,   // EINVAL
+        MmapError::InvalidRange => -22,  // EINVAL
+        MmapError::Overlap => -17,       // EEXIST
+        MmapError::Reserved => -1,       // EPERM
+        MmapError::OutOfMemory => -12,   // ENOMEM
+        MmapError::NotMapped => -14,     // EFAULT
+        MmapError::LimitExceeded => -12, // ENOMEM
+        MmapError::WriteExecute => -13,  // EACCES
+    }
+}
+
+/// 设置 program break 为 new_brk 并返回新的 program break，new_brk 为 0 时仅返回当前的 program break
+pub fn sys_brk(new_brk: isize) -> isize {
+    if new_brk == 0 {
+        return get_program_break() as isize;
+    }
+    if new_brk < 0 {
         return -1;
     }
-    
-    mmap(_start, _len, _port)
+    match set_program_break(new_brk as usize) {
+        Some(brk) => brk as isize,
+        None => -1,
+    }
+}
+
+/// 返回当前的 program break，即堆的顶部；尚未扩展堆时就是用户栈的栈顶，按页对齐且位于所有已加载的段之上
+pub fn sys_get_brk() -> isize {
+    get_program_break() as isize
+}
+
+/// 将 program break 移动 increment 个字节，返回移动之前的 program break
+pub fn sys_sbrk(increment: isize) -> isize {
+    match change_program_break(increment) {
+        Some(old_brk) => old_brk as isize,
+        None => -1,
+    }
 }
 
+/// 取消映射 [_start, _start + _len)，_len 不是页面大小的整数倍时向上取整到整页
+///
+/// _len 为 0 时与其他非法的范围一样返回 -1，以暴露调用者的错误，而不是静默地成功
 pub fn sys_munmap(_start: usize, _len: usize) -> isize {
-    // _start 要按页对齐
-    if _start & (PAGE_SIZE - 1) != 0 {
-        return -1;
+    match munmap(_start, _len) {
+        Ok(()) => 0,
+        Err(_) => -1,
     }
-    munmap(_start, _len)
+}
+
+pub fn clock_gettime_test() {
+    let mut prev = monotonic_time();
+    for _ in 0..1000 {
+        let ts = monotonic_time();
+        assert!(ts.nsec < 1_000_000_000);
+        assert!((ts.sec, ts.nsec) >= (prev.sec, prev.nsec));
+        prev = ts;
+    }
+    info!("clock_gettime_test passed!");
+}
+
+pub fn query_page_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let (code, data) = (0x10000, 0x20000);
+    memory_set.insert_framed_area(
+        code.into(),
+        (code + 0x1000).into(),
+        MapPermission::R | MapPermission::X | MapPermission::U,
+    );
+    memory_set.insert_framed_area(
+        data.into(),
+        (data + 0x1000).into(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    let token = memory_set.token();
+    let flags = |flags: PTEFlags| (flags | PTEFlags::V | PTEFlags::U).bits() as isize;
+    assert_eq!(
+        query_page(token, code + 8),
+        flags(PTEFlags::R | PTEFlags::X)
+    );
+    assert_eq!(query_page(token, data), flags(PTEFlags::R | PTEFlags::W));
+    assert_eq!(query_page(token, data + 0x1000), -1);
+    assert_eq!(query_page(token, usize::MAX), -1);
+    // 应用地址空间中的 Trap 上下文和跳板不带 U 位，视为未映射
+    let (memory_set, _, _) = MemorySet::from_elf(crate::loader::get_app_data(0)).unwrap();
+    assert_eq!(query_page(memory_set.token(), TRAP_CONTEXT), -1);
+    assert_eq!(query_page(memory_set.token(), usize::MAX), -1);
+    info!("query_page_test passed!");
+}
+
+pub fn getpid_test() {
+    let pid = sys_getpid();
+    assert_eq!(pid, current_pid() as isize);
+    assert_eq!(sys_getpid(), pid);
+    info!("getpid_test passed!");
+}
+
+pub fn mmap_errno_test() {
+    // 由地址空间实际返回的错误得到 errno，而不是逐项复述转换表
+    let mut memory_set = MemorySet::new_bare();
+    let start = 0x10000;
+    let errno = |result: Result<usize, MmapError>| result.map_or_else(mmap_errno, |_| 0);
+    assert_eq!(errno(memory_set.mmap(start + 1, PAGE_SIZE, 0b011)), -22);
+    assert_eq!(errno(memory_set.mmap(start, PAGE_SIZE, 0)), -22);
+    assert_eq!(errno(memory_set.mmap(start, usize::MAX, 0b011)), -22);
+    assert_eq!(errno(memory_set.mmap(TRAP_CONTEXT, PAGE_SIZE, 0b011)), -1);
+    assert_eq!(errno(memory_set.mmap(start, PAGE_SIZE, 0b011)), 0);
+    assert_eq!(errno(memory_set.mmap(start, PAGE_SIZE, 0b011)), -17);
+    assert_eq!(
+        memory_set
+            .munmap(start + PAGE_SIZE, PAGE_SIZE)
+            .map_or_else(mmap_errno, |_| 0),
+        -14
+    );
+    assert_eq!(mmap_errno(MmapError::OutOfMemory), -12);
+    assert_eq!(mmap_errno(MmapError::LimitExceeded), -12);
+    assert_eq!(mmap_errno(MmapError::WriteExecute), -13);
+    info!("mmap_errno_test passed!");
 }
@@ -16,6 +16,7 @@ mod task;
 
 use crate::config::MAX_SYSCALL_NUM;
 use crate::loader::{get_app_data, get_num_app};
+use crate::mm::{MmapError, MmapFlags};
 use crate::sync::UPSafeCell;
 use crate::trap::TrapContext;
 use alloc::vec::Vec;
@@ -57,10 +58,14 @@ lazy_static! {
         info!("num_app = {}", num_app);
         let mut tasks: Vec<TaskControlBlock> = Vec::new();
         for i in 0..num_app {
-            tasks.push(TaskControlBlock::new(get_app_data(i), i));
+            // 不合法的 ELF 直接拒绝，不加入任务列表
+            match TaskControlBlock::new(get_app_data(i)) {
+                Ok(task) => tasks.push(task),
+                Err(err) => error!("[kernel] app {} rejected: {}", i, err),
+            }
         }
         TaskManager {
-            num_app,
+            num_app: tasks.len(),
             inner: unsafe {
                 UPSafeCell::new(TaskManagerInner {
                     tasks,
@@ -149,6 +154,12 @@ impl TaskManager {
         }
     }
 
+    // 获得当前 task 的 pid
+    fn get_current_pid(&self) -> usize {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].pid
+    }
+
     // 获得当前 task 状态
     fn get_task_status(&self) -> TaskStatus {
         let mut inner = self.inner.exclusive_access();
@@ -163,11 +174,20 @@ impl TaskManager {
         inner.tasks[cur_task].syscall_times
     }
 
-    // 增加当前系统调用的次数
+    // 增加当前系统调用的次数，超出统计范围的 syscall_id 不计数
     fn add_syscall_times(&self, syscall_id: usize) {
         let mut inner = self.inner.exclusive_access();
         let cur_task = inner.current_task;
-        inner.tasks[cur_task].syscall_times[syscall_id] += 1;
+        if let Some(times) = inner.tasks[cur_task].syscall_times.get_mut(syscall_id) {
+            *times += 1;
+        }
+    }
+
+    // 将当前 task 的系统调用次数全部清零
+    fn reset_syscall_times(&self) {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        inner.tasks[cur_task].syscall_times = [0; MAX_SYSCALL_NUM];
     }
 
     // 获得当前 task 的开始时间
@@ -177,21 +197,109 @@ impl TaskManager {
         inner.tasks[cur_task].start_time
     }
 
+    // 记录当前 task 最近一次缺页异常的地址和原因
+    fn record_fault(&self, addr: usize, cause: usize) {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        inner.tasks[cur_task].last_fault_addr = addr;
+        inner.tasks[cur_task].last_fault_cause = cause;
+    }
+
+    // 获得当前 task 最近一次缺页异常的地址和原因
+    fn get_last_fault(&self) -> (usize, usize) {
+        let inner = self.inner.exclusive_access();
+        let task = &inner.tasks[inner.current_task];
+        (task.last_fault_addr, task.last_fault_cause)
+    }
+
+    // 获得当前 task 的 program break
+    fn get_program_break(&self) -> usize {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].program_break
+    }
+
+    // 修改当前 task 的 program break
+    fn set_program_break(&self, new_brk: usize) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        inner.tasks[cur_task].set_program_break(new_brk)
+    }
+
+    // 将当前 task 的 program break 移动 increment 个字节
+    fn change_program_break(&self, increment: isize) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        inner.tasks[cur_task].change_program_break(increment)
+    }
+
+    // 获得当前 task 占用物理页帧数的峰值
+    fn get_peak_frames(&self) -> usize {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].peak_frames
+    }
+
+    // 获得当前 task 被成功处理的缺页异常次数
+    fn get_page_faults(&self) -> usize {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].page_faults
+    }
+
+    // 获得当前 task 正在占用的物理页帧数
+    fn get_resident_frames(&self) -> usize {
+        let inner = self.inner.exclusive_access();
+        inner.tasks[inner.current_task].memory_set.resident_frames()
+    }
+
     // mmap
-    fn mmap(&self, start: usize, len: usize, port: usize) -> isize {
+    fn mmap(&self, start: usize, len: usize, port: usize) -> Result<usize, MmapError> {
         let mut inner = self.inner.exclusive_access();
         let cur_task = inner.current_task;
-        let memory_set = &mut inner.tasks[cur_task].memory_set;
-        memory_set.mmap(start, len, port)
+        inner.tasks[cur_task].mmap(start, len, port)
+    }
+
+    // start 只是建议的地址，与已有映射重叠时向上寻找空闲区域
+    fn mmap_hint(&self, start: usize, len: usize, port: usize) -> Result<usize, MmapError> {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        inner.tasks[cur_task].mmap_with(start, len, port, MmapFlags::HINT)
     }
 
     // munmap
-    fn munmap(&self, start: usize, len: usize) -> isize {
+    fn munmap(&self, start: usize, len: usize) -> Result<(), MmapError> {
         let mut inner = self.inner.exclusive_access();
         let cur_task = inner.current_task;
         let memory_set = &mut inner.tasks[cur_task].memory_set;
         memory_set.munmap(start, len)
     }
+
+    // 一次映射多个区域，要么全部成功，要么全部撤销
+    fn mmap_batch(&self, specs: &[(usize, usize, usize)]) -> Result<(), MmapError> {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        inner.tasks[cur_task].mmap_batch(specs)
+    }
+
+    // 丢弃当前 task 地址空间中一段页面的物理页帧，保留其逻辑段
+    fn madvise_dontneed(&self, start: usize, len: usize) -> Result<(), MmapError> {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        let memory_set = &mut inner.tasks[cur_task].memory_set;
+        memory_set.madvise_dontneed(start, len)
+    }
+
+    // 修改当前 task 地址空间中一段页面的访问权限
+    fn mprotect(&self, start: usize, len: usize, port: usize) -> Result<(), MmapError> {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        inner.tasks[cur_task].mprotect(start, len, port)
+    }
+
+    // 处理当前 task 的缺页异常，返回是否已经补上映射
+    fn handle_page_fault(&self, addr: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let cur_task = inner.current_task;
+        inner.tasks[cur_task].handle_page_fault(addr)
+    }
 }
 
 /// Run the first task in task list.
@@ -237,6 +345,10 @@ pub fn current_trap_cx() -> &'static mut TrapContext {
     TASK_MANAGER.get_current_trap_cx()
 }
 
+pub fn current_pid() -> usize {
+    TASK_MANAGER.get_current_pid()
+}
+
 // os4 实验任务，获取当前任务信息
 pub fn get_task_status() -> TaskStatus {
     TASK_MANAGER.get_task_status()
@@ -250,14 +362,83 @@ pub fn add_syscall_times(syscall_id: usize) {
     TASK_MANAGER.add_syscall_times(syscall_id);
 }
 
+pub fn reset_syscall_times() {
+    TASK_MANAGER.reset_syscall_times();
+}
+
 pub fn get_start_time() -> usize {
     TASK_MANAGER.get_start_time()
 }
 
-pub fn mmap(start: usize, len: usize, port: usize) -> isize {
+pub fn get_peak_frames() -> usize {
+    TASK_MANAGER.get_peak_frames()
+}
+
+pub fn get_resident_frames() -> usize {
+    TASK_MANAGER.get_resident_frames()
+}
+
+pub fn get_page_faults() -> usize {
+    TASK_MANAGER.get_page_faults()
+}
+
+pub fn record_fault(addr: usize, cause: usize) {
+    TASK_MANAGER.record_fault(addr, cause);
+}
+
+pub fn get_last_fault() -> (usize, usize) {
+    TASK_MANAGER.get_last_fault()
+}
+
+pub fn get_program_break() -> usize {
+    TASK_MANAGER.get_program_break()
+}
+
+pub fn set_program_break(new_brk: usize) -> Option<usize> {
+    TASK_MANAGER.set_program_break(new_brk)
+}
+
+pub fn change_program_break(increment: isize) -> Option<usize> {
+    TASK_MANAGER.change_program_break(increment)
+}
+
+pub fn mmap(start: usize, len: usize, port: usize) -> Result<usize, MmapError> {
     TASK_MANAGER.mmap(start, len, port)
 }
 
-pub fn munmap(start: usize, len: usize) -> isize {
+pub fn mmap_hint(start: usize, len: usize, port: usize) -> Result<usize, MmapError> {
+    TASK_MANAGER.mmap_hint(start, len, port)
+}
+
+pub fn munmap(start: usize, len: usize) -> Result<(), MmapError> {
     TASK_MANAGER.munmap(start, len)
-}
\ No newline at end of file
+}
+
+pub fn mmap_batch(specs: &[(usize, usize, usize)]) -> Result<(), MmapError> {
+    TASK_MANAGER.mmap_batch(specs)
+}
+
+pub fn madvise_dontneed(start: usize, len: usize) -> Result<(), MmapError> {
+    TASK_MANAGER.madvise_dontneed(start, len)
+}
+
+pub fn mprotect(start: usize, len: usize, port: usize) -> Result<(), MmapError> {
+    TASK_MANAGER.mprotect(start, len, port)
+}
+
+pub fn handle_page_fault(addr: usize) -> bool {
+    TASK_MANAGER.handle_page_fault(addr)
+}
+
+/// 依次运行任务管理相关的测试，需要在运行第一个任务之前调用
+pub fn run_tests() {
+    task::kernel_stack_test();
+    task::exec_statistics_test();
+    task::as_limit_test();
+    task::program_break_test();
+    task::page_fault_count_test();
+    task::mmap_batch_test();
+    task::pid_test();
+    task::kernel_stack_allocator_test();
+    task::wx_strict_test();
+}
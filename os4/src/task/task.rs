@@ -1,17 +1,34 @@
 //! Types related to task management
 use super::TaskContext;
-use crate::config::{kernel_stack_position, TRAP_CONTEXT, MAX_SYSCALL_NUM};
-use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::config::{
+    kernel_stack_position, MAX_KERNEL_STACKS, MAX_SYSCALL_NUM, PAGE_SIZE, TRAP_CONTEXT,
+    USER_AS_LIMIT, WX_STRICT,
+};
+use crate::loader::{get_app_data, get_num_app};
+use crate::mm::{
+    MapPermission, MemorySet, MmapError, MmapFlags, PhysPageNum, VirtAddr, KERNEL_SPACE,
+};
+use crate::sync::UPSafeCell;
 use crate::trap::{trap_handler, TrapContext};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+// 下一个要分配的 pid，只增不减，保证每个任务的 pid 各不相同
+static NEXT_PID: AtomicUsize = AtomicUsize::new(0);
 
 /// task control block structure
 pub struct TaskControlBlock {
+    // 创建任务时分配的唯一标识，不会被复用
+    pub pid: usize,
     pub task_status: TaskStatus,
     pub task_cx: TaskContext,
     // 应用的地址空间
     pub memory_set: MemorySet,
     // 位于应用地址空间次高页的 Trap 上下文被实际存放在物理页帧的物理页号
     pub trap_cx_ppn: PhysPageNum,
+    // 应用在内核地址空间中的内核栈，随任务一起回收
+    pub kernel_stack: KernelStack,
     // 统计了应用数据的大小，也就是在应用地址空间中从 0x0 开始到用户栈结束一共包含多少字节
     pub base_size: usize,
 
@@ -19,6 +36,24 @@ pub struct TaskControlBlock {
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
     // 记录开始时间，便于管理时间片
     pub start_time: usize,
+
+    // 堆的底部，紧接在用户栈之上
+    pub heap_bottom: usize,
+    // 当前的 program break，即堆的顶部
+    pub program_break: usize,
+
+    // 地址空间曾经占用过的物理页帧数的最大值
+    pub peak_frames: usize,
+    // mmap 之后虚拟地址空间的总大小不能超过这个上限，防止失控的分配耗尽内存
+    pub as_limit: usize,
+    // 为 true 时拒绝同时要求 W 和 X 权限的 mmap 和 mprotect
+    pub wx_strict: bool,
+
+    // 最近一次缺页异常的地址和原因（scause），便于调试
+    pub last_fault_addr: usize,
+    pub last_fault_cause: usize,
+    // 被内核成功处理（补上映射后继续运行）的缺页异常次数，不包括导致任务被杀死的缺页异常
+    pub page_faults: usize,
 }
 
 impl TaskControlBlock {
@@ -32,11 +67,164 @@ impl TaskControlBlock {
         self.memory_set.token()
     }
 
+    /// 根据当前地址空间占用的物理页帧数更新峰值
+    pub fn update_peak_frames(&mut self) {
+        self.peak_frames = self.peak_frames.max(self.memory_set.resident_frames());
+    }
+
+    /// 在地址空间中映射 [start, start + len)，映射之后虚拟地址空间的总大小超过 as_limit 时返回 Err(MmapError::LimitExceeded)
+    ///
+    /// 开启 wx_strict 时 port 同时包含 W 和 X 则返回 Err(MmapError::WriteExecute)
+    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> Result<usize, MmapError> {
+        self.mmap_with(start, len, port, MmapFlags::empty())
+    }
+
+    /// 与 mmap 相同，flags 给出 port 之外的选项
+    pub fn mmap_with(
+        &mut self,
+        start: usize,
+        len: usize,
+        port: usize,
+        flags: MmapFlags,
+    ) -> Result<usize, MmapError> {
+        self.check_wx(port)?;
+        // 大小溢出的请求交给 MemorySet::mmap 作为非法的范围拒绝
+        let total = VirtAddr::from(len)
+            .ceil()
+            .0
+            .checked_mul(PAGE_SIZE)
+            .and_then(|size| size.checked_add(self.memory_set.virtual_size()));
+        if total.map_or(false, |total| total > self.as_limit) {
+            return Err(MmapError::LimitExceeded);
+        }
+        let start = self.memory_set.mmap_with(start, len, port, flags)?;
+        self.update_peak_frames();
+        Ok(start)
+    }
+
+    /// 修改 [start, start + len) 中页面的访问权限，与 mmap 一样受 wx_strict 的限制
+    pub fn mprotect(&mut self, start: usize, len: usize, port: usize) -> Result<(), MmapError> {
+        self.check_wx(port)?;
+        self.memory_set.mprotect(start, len, port)
+    }
+
+    // port 的第 1 位和第 2 位分别表示 W 和 X
+    fn check_wx(&self, port: usize) -> Result<(), MmapError> {
+        if self.wx_strict && port & 0b110 == 0b110 {
+            Err(MmapError::WriteExecute)
+        } else {
+            Ok(())
+        }
+    }
 
-    pub fn new(elf_data: &[u8], app_id: usize) -> Self {
+    /// 依次映射 specs 中的每个 (start, len, port)，任意一个失败时撤销本次已经完成的映射并返回该错误
+    pub fn mmap_batch(&mut self, specs: &[(usize, usize, usize)]) -> Result<(), MmapError> {
+        let mut mapped = Vec::with_capacity(specs.len());
+        for &(start, len, port) in specs {
+            match self.mmap(start, len, port) {
+                Ok(start) => mapped.push(start),
+                Err(err) => {
+                    for start in mapped {
+                        self.memory_set
+                            .remove_area_with_start_vpn(VirtAddr::from(start).floor());
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 处理地址空间中 addr 处的缺页异常，补上映射时计入 page_faults 并返回 true
+    pub fn handle_page_fault(&mut self, addr: usize) -> bool {
+        let handled = self.memory_set.handle_page_fault(addr.into());
+        if handled {
+            self.page_faults += 1;
+            self.update_peak_frames();
+        }
+        handled
+    }
+
+    /// 将 program break 设置为 new_brk，成功时返回新的 program break
+    pub fn set_program_break(&mut self, new_brk: usize) -> Option<usize> {
+        if new_brk < self.heap_bottom {
+            return None;
+        }
+        let heap_bottom = VirtAddr::from(self.heap_bottom);
+        let result = if new_brk < self.program_break {
+            self.memory_set
+                .shrink_to(heap_bottom, VirtAddr::from(new_brk))
+        } else {
+            self.memory_set
+                .append_to(heap_bottom, VirtAddr::from(new_brk))
+        };
+        if result {
+            self.program_break = new_brk;
+            self.update_peak_frames();
+            Some(new_brk)
+        } else {
+            None
+        }
+    }
+
+    /// 将 program break 移动 increment 个字节，成功时返回移动之前的 program break
+    pub fn change_program_break(&mut self, increment: isize) -> Option<usize> {
+        let old_brk = self.program_break;
+        let new_brk = if increment >= 0 {
+            old_brk.checked_add(increment as usize)?
+        } else {
+            old_brk.checked_sub(increment.unsigned_abs())?
+        };
+        self.set_program_break(new_brk)?;
+        Some(old_brk)
+    }
+
+    /// 用 elf_data 替换当前任务的地址空间，沿用原有的内核栈和页表根节点
+    ///
+    /// ELF 不合法时返回 Err，此时原有的地址空间保持不变
+    ///
+    /// exec 前后仍是同一个任务，syscall_times 和 start_time 被保留而不是清零，
+    /// 因此 sys_task_info 返回的系统调用次数和运行时间包含 exec 之前的部分
+    pub fn exec(&mut self, elf_data: &[u8]) -> Result<(), &'static str> {
+        MemorySet::parse_elf(elf_data)?;
+        // 内核栈位于内核地址空间中，不随用户地址空间回收，先记下其栈顶
+        let kernel_sp = self.get_trap_cx().kernel_sp;
+        self.memory_set.recycle_data_pages();
+        let (user_sp, entry_point) = self.memory_set.load_elf(elf_data)?;
+        self.trap_cx_ppn = self
+            .memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        self.base_size = user_sp;
+        // syscall_times 和 start_time 属于任务而不是地址空间，这里不做修改
+
+        // 重新在用户栈之上放置空堆
+        self.heap_bottom = user_sp;
+        self.program_break = user_sp;
+        self.memory_set.insert_framed_area(
+            user_sp.into(),
+            user_sp.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
+        self.update_peak_frames();
+
+        let trap_cx = self.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.lock().token(),
+            kernel_sp,
+            trap_handler as usize,
+        );
+        Ok(())
+    }
+
+    /// ELF 不合法或者内核栈槽位耗尽时返回 Err
+    pub fn new(elf_data: &[u8]) -> Result<Self, &'static str> {
         // memory_set with elf program headers/trampoline/trap context/user stack
         // 解析传入的 ELF 格式数据构造应用的地址空间 memory_set 并获得其他信息
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (mut memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data)?;
         // 从地址空间 memory_set 中查多级页表找到应用地址空间中的 Trap 上下文实际被放在哪个物理页帧
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
@@ -44,23 +232,37 @@ impl TaskControlBlock {
             .ppn();
         let task_status = TaskStatus::Ready;
 
-        // map a kernel-stack in kernel space
-        // 根据传入的应用 ID app_id 调用在 config 子模块中定义的 kernel_stack_position 找到 应用的内核栈预计放在内核地址空间 KERNEL_SPACE 中的哪个位置，并通过 insert_framed_area 实际将这个逻辑段 加入到内核地址空间中
-        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(app_id);
-        KERNEL_SPACE.lock().insert_framed_area(
-            kernel_stack_bottom.into(),
-            kernel_stack_top.into(),
-            MapPermission::R | MapPermission::W,
+        // 在用户栈之上放置一个初始为空的堆，之后由 sys_brk 扩展或缩小
+        let heap_bottom = user_sp;
+        memory_set.insert_framed_area(
+            heap_bottom.into(),
+            heap_bottom.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
         );
 
+        // map a kernel-stack in kernel space
+        let kernel_stack = KernelStack::new().ok_or("no free kernel stack")?;
+        let kernel_stack_top = kernel_stack.get_top();
+
+        let peak_frames = memory_set.resident_frames();
         let task_control_block = Self {
+            pid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
             task_status,
             task_cx: TaskContext::goto_trap_return(kernel_stack_top),
             memory_set,
             trap_cx_ppn,
+            kernel_stack,
             base_size: user_sp,
             syscall_times: [0; MAX_SYSCALL_NUM],
-            start_time: 0
+            start_time: 0,
+            heap_bottom,
+            program_break: heap_bottom,
+            peak_frames,
+            as_limit: USER_AS_LIMIT,
+            wx_strict: WX_STRICT,
+            last_fault_addr: 0,
+            last_fault_cause: 0,
+            page_faults: 0,
         };
         // prepare TrapContext in user space
         let trap_cx = task_control_block.get_trap_cx();
@@ -71,7 +273,88 @@ impl TaskControlBlock {
             kernel_stack_top,
             trap_handler as usize,
         );
-        task_control_block
+        Ok(task_control_block)
+    }
+}
+
+/// 内核栈槽位的分配器，与 StackFrameAllocator 一样优先复用已回收的槽位，使内核栈的位置与 app_id 无关
+pub struct KernelStackAllocator {
+    // [current, end) 中的槽位此前从未被分配出去过
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl KernelStackAllocator {
+    /// 管理槽位 [0, end)
+    pub fn new(end: usize) -> Self {
+        Self {
+            current: 0,
+            end,
+            recycled: Vec::new(),
+        }
+    }
+
+    /// 优先复用最近回收的槽位，槽位耗尽时返回 None
+    pub fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current < self.end {
+            self.current += 1;
+            Some(self.current - 1)
+        } else {
+            None
+        }
+    }
+
+    /// 回收一个槽位，它必须已经被分配出去且尚未被回收
+    pub fn dealloc(&mut self, slot: usize) {
+        if slot >= self.current || self.recycled.contains(&slot) {
+            panic!("Kernel stack slot {} has not been allocated!", slot);
+        }
+        self.recycled.push(slot);
+    }
+}
+
+lazy_static! {
+    static ref KERNEL_STACK_ALLOCATOR: UPSafeCell<KernelStackAllocator> =
+        unsafe { UPSafeCell::new(KernelStackAllocator::new(MAX_KERNEL_STACKS)) };
+}
+
+/// 应用的内核栈，被回收时将其从内核地址空间中移除并归还槽位，避免反复创建任务时泄漏物理页帧
+pub struct KernelStack {
+    slot: usize,
+}
+
+impl KernelStack {
+    /// 分配一个空闲的槽位，调用在 config 子模块中定义的 kernel_stack_position 找到内核栈在内核地址空间 KERNEL_SPACE 中的位置，并通过 insert_framed_area 实际将这个逻辑段加入到内核地址空间中
+    ///
+    /// 槽位耗尽时返回 None
+    pub fn new() -> Option<Self> {
+        let slot = KERNEL_STACK_ALLOCATOR.exclusive_access().alloc()?;
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(slot);
+        KERNEL_SPACE.lock().insert_framed_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        );
+        Some(Self { slot })
+    }
+
+    pub fn get_top(&self) -> usize {
+        let (_, kernel_stack_top) = kernel_stack_position(self.slot);
+        kernel_stack_top
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let (kernel_stack_bottom, _) = kernel_stack_position(self.slot);
+        let removed = KERNEL_SPACE
+            .lock()
+            .remove_area_with_start_vpn(VirtAddr::from(kernel_stack_bottom).floor());
+        assert!(removed, "kernel stack is not mapped");
+        KERNEL_STACK_ALLOCATOR.exclusive_access().dealloc(self.slot);
     }
 }
 
@@ -83,3 +366,203 @@ pub enum TaskStatus {
     Running,
     Exited,
 }
+
+// 测试中使用的任务，以及其地址空间中一段至少 32 页的空闲区域的起始地址
+fn test_task() -> (TaskControlBlock, usize) {
+    let task = TaskControlBlock::new(get_app_data(0)).unwrap();
+    let start = task
+        .memory_set
+        .find_free_range(VirtAddr::from(0x10000000).floor(), 32)
+        .unwrap();
+    (task, VirtAddr::from(start).into())
+}
+
+pub fn kernel_stack_test() {
+    let baseline = KERNEL_SPACE.lock().resident_frames();
+    for _ in 0..3 {
+        let tasks: Vec<TaskControlBlock> = (0..4).map(|_| test_task().0).collect();
+        assert!(KERNEL_SPACE.lock().resident_frames() > baseline);
+        drop(tasks);
+        assert_eq!(KERNEL_SPACE.lock().resident_frames(), baseline);
+    }
+    info!("kernel_stack_test passed!");
+}
+
+pub fn exec_statistics_test() {
+    let (mut task, _) = test_task();
+    // 模拟 exec 之前发起过 3 次 sys_write 和 1 次 sys_get_time
+    task.syscall_times[64] = 3;
+    task.syscall_times[169] = 1;
+    task.start_time = 42;
+    let syscall_times = task.syscall_times;
+    // 模拟任务已经运行了一段时间，sepc 不再指向原来的入口
+    task.get_trap_cx().sepc = 0xdead0;
+    let token = task.get_user_token();
+    let elf_data = get_app_data(get_num_app() - 1);
+    let entry_point = MemorySet::parse_elf(elf_data)
+        .unwrap()
+        .header
+        .pt2
+        .entry_point() as usize;
+    task.exec(elf_data).unwrap();
+    // sys_task_info 直接读取这两个字段，exec 之前的统计应当仍然可见
+    assert_eq!(task.syscall_times, syscall_times);
+    assert_eq!(task.start_time, 42);
+    // 从新 ELF 的入口开始执行，根页表被复用因此 token 不变，trap_cx_ppn 指向新的 Trap 上下文页帧
+    assert_eq!(task.get_trap_cx().sepc, entry_point);
+    assert_eq!(task.get_user_token(), token);
+    assert_eq!(
+        task.trap_cx_ppn,
+        task.memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn()
+    );
+    info!("exec_statistics_test passed!");
+}
+
+pub fn as_limit_test() {
+    let (mut task, start) = test_task();
+    task.as_limit = task.memory_set.virtual_size() + 3 * PAGE_SIZE;
+    // 恰好到达上限
+    assert_eq!(task.mmap(start, 2 * PAGE_SIZE, 0b011), Ok(start));
+    assert_eq!(
+        task.mmap(start + 2 * PAGE_SIZE, PAGE_SIZE, 0b011),
+        Ok(start + 2 * PAGE_SIZE)
+    );
+    // 超过上限，不足一页的部分按整页计算
+    assert_eq!(
+        task.mmap(start + 3 * PAGE_SIZE, 1, 0b011),
+        Err(MmapError::LimitExceeded)
+    );
+    // munmap 之后又有了空间
+    assert_eq!(task.memory_set.munmap(start, PAGE_SIZE), Ok(()));
+    assert_eq!(
+        task.mmap(start + 3 * PAGE_SIZE, 1, 0b011),
+        Ok(start + 3 * PAGE_SIZE)
+    );
+    info!("as_limit_test passed!");
+}
+
+pub fn program_break_test() {
+    for app_id in 0..get_num_app() {
+        let task = TaskControlBlock::new(get_app_data(app_id)).unwrap();
+        let brk = task.program_break;
+        assert_eq!(brk % PAGE_SIZE, 0);
+        // 起始于 program break 之下的逻辑段（ELF 中的各个段和用户栈）都在其之下结束
+        for (start, end, _, _) in task.memory_set.describe() {
+            if start.0 < brk {
+                assert!(end.0 <= brk);
+            }
+        }
+    }
+    info!("program_break_test passed!");
+}
+
+pub fn page_fault_count_test() {
+    let (mut task, start) = test_task();
+    let pages = 4;
+    assert_eq!(task.mmap(start, pages * PAGE_SIZE, 0b011), Ok(start));
+    // 丢弃页帧之后，每个页面第一次被访问时触发一次缺页异常
+    assert_eq!(
+        task.memory_set.madvise_dontneed(start, pages * PAGE_SIZE),
+        Ok(())
+    );
+    for i in 0..pages {
+        assert!(task.handle_page_fault(start + i * PAGE_SIZE + 8));
+    }
+    assert_eq!(task.page_faults, pages);
+    // 已经补上映射的页面和不属于任何逻辑段的地址都不计入
+    assert!(!task.handle_page_fault(start));
+    assert!(!task.handle_page_fault(start + pages * PAGE_SIZE));
+    assert_eq!(task.page_faults, pages);
+    info!("page_fault_count_test passed!");
+}
+
+pub fn mmap_batch_test() {
+    let (mut task, start) = test_task();
+    let specs = [
+        (start, PAGE_SIZE, 0b011),
+        (start + 2 * PAGE_SIZE, 2 * PAGE_SIZE, 0b001),
+        (start + 8 * PAGE_SIZE, PAGE_SIZE, 0b111),
+    ];
+    assert_eq!(task.mmap_batch(&specs), Ok(()));
+    for &(start, len, _) in specs.iter() {
+        assert!(task
+            .memory_set
+            .range_is_mapped(start, len, MapPermission::R | MapPermission::U));
+    }
+    // 中间一项的 port 不合法，已经完成的第一项也被撤销
+    let other = start + 16 * PAGE_SIZE;
+    let before = task.memory_set.describe();
+    let specs = [
+        (other, PAGE_SIZE, 0b011),
+        (other + PAGE_SIZE, PAGE_SIZE, 0),
+        (other + 2 * PAGE_SIZE, PAGE_SIZE, 0b011),
+    ];
+    assert_eq!(task.mmap_batch(&specs), Err(MmapError::InvalidPort));
+    assert_eq!(task.memory_set.describe(), before);
+    assert!(!task
+        .memory_set
+        .range_is_mapped(other, PAGE_SIZE, MapPermission::empty()));
+    info!("mmap_batch_test passed!");
+}
+
+pub fn pid_test() {
+    let (first, _) = test_task();
+    let (second, _) = test_task();
+    // 即使由同一个应用创建，pid 也各不相同且按创建顺序递增
+    assert!(first.pid < second.pid);
+    let pid = second.pid;
+    drop(second);
+    let (third, _) = test_task();
+    assert!(third.pid > pid);
+    info!("pid_test passed!");
+}
+
+pub fn kernel_stack_allocator_test() {
+    let mut allocator = KernelStackAllocator::new(2);
+    assert_eq!(allocator.alloc(), Some(0));
+    assert_eq!(allocator.alloc(), Some(1));
+    assert_eq!(allocator.alloc(), None);
+    allocator.dealloc(0);
+    assert_eq!(allocator.alloc(), Some(0));
+    assert_eq!(allocator.alloc(), None);
+
+    // 依次创建并回收比槽位数更多的任务，槽位被回收后可以继续创建
+    for _ in 0..MAX_KERNEL_STACKS + 1 {
+        drop(test_task());
+    }
+    // 同时存在的任务的内核栈互不重叠
+    let (first, _) = test_task();
+    let (second, _) = test_task();
+    assert_ne!(first.kernel_stack.get_top(), second.kernel_stack.get_top());
+    info!("kernel_stack_allocator_test passed!");
+}
+
+pub fn wx_strict_test() {
+    let (mut task, start) = test_task();
+    task.wx_strict = true;
+    assert_eq!(task.mmap(start, PAGE_SIZE, 0b101), Ok(start));
+    let rw = start + PAGE_SIZE;
+    assert_eq!(task.mmap(rw, PAGE_SIZE, 0b011), Ok(rw));
+    let wx = start + 2 * PAGE_SIZE;
+    assert_eq!(
+        task.mmap(wx, PAGE_SIZE, 0b110),
+        Err(MmapError::WriteExecute)
+    );
+    assert_eq!(
+        task.mmap(wx, PAGE_SIZE, 0b111),
+        Err(MmapError::WriteExecute)
+    );
+    // 同样不能通过 mprotect 把已有的区域改为可写可执行
+    assert_eq!(
+        task.mprotect(rw, PAGE_SIZE, 0b111),
+        Err(MmapError::WriteExecute)
+    );
+    assert_eq!(task.mprotect(rw, PAGE_SIZE, 0b101), Ok(()));
+    // 关闭之后允许 RWX 的区域
+    task.wx_strict = false;
+    assert_eq!(task.mmap(wx, PAGE_SIZE, 0b111), Ok(wx));
+    info!("wx_strict_test passed!");
+}
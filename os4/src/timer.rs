@@ -4,6 +4,7 @@ use riscv::register::time;
 
 const TICKS_PER_SEC: usize = 100;
 const MICRO_PER_SEC: usize = 1_000_000;
+const NANO_PER_SEC: usize = 1_000_000_000;
 
 pub fn get_time() -> usize {
     time::read()
@@ -13,6 +14,12 @@ pub fn get_time_us() -> usize {
     time::read() / (CLOCK_FREQ / MICRO_PER_SEC)
 }
 
+/// 开机以来的纳秒数，先拆出整秒部分再换算，避免 time 乘以 NANO_PER_SEC 时溢出
+pub fn get_time_ns() -> usize {
+    let time = time::read();
+    time / CLOCK_FREQ * NANO_PER_SEC + time % CLOCK_FREQ * NANO_PER_SEC / CLOCK_FREQ
+}
+
 pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
@@ -16,7 +16,8 @@ mod context;
 use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
 use crate::syscall::syscall;
 use crate::task::{
-    current_trap_cx, current_user_token, exit_current_and_run_next, suspend_current_and_run_next,
+    current_trap_cx, current_user_token, exit_current_and_run_next, handle_page_fault,
+    record_fault, suspend_current_and_run_next,
 };
 use crate::timer::set_next_trigger;
 use riscv::register::{
@@ -62,8 +63,14 @@ pub fn trap_handler() -> ! {
         }
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::LoadPageFault) => {
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            // 访问（包括取指）被 madvise 丢弃了页帧的页面时重新分配一个清零的页帧，然后重新执行出错的指令
+            if handle_page_fault(stval) {
+                trap_return();
+            }
             error!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
+            record_fault(stval, scause.bits());
             exit_current_and_run_next();
         }
         Trap::Exception(Exception::IllegalInstruction) => {
@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{clock_gettime, TimeSpec, CLOCK_MONOTONIC};
+
+/*
+理想结果：单调时钟的纳秒部分不超过 1 秒且读数不会倒退，输出 Test 04_clock_gettime OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let mut prev = TimeSpec::new();
+    assert_eq!(clock_gettime(CLOCK_MONOTONIC, &mut prev), 0);
+    for _ in 0..100 {
+        let mut ts = TimeSpec::new();
+        assert_eq!(clock_gettime(CLOCK_MONOTONIC, &mut ts), 0);
+        assert!(ts.nsec < 1_000_000_000);
+        assert!((ts.sec, ts.nsec) >= (prev.sec, prev.nsec));
+        prev = ts;
+    }
+    // 不支持的时钟
+    assert_eq!(clock_gettime(12345, &mut prev), -1);
+    println!("Test 04_clock_gettime OK!");
+    0
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_brk, sbrk};
+
+/*
+理想结果：program break 按页对齐且位于代码段和数据段之上，并随 sbrk 移动，输出 Test 04_get_brk OK!
+*/
+
+static mut DATA: [u8; 16] = [1; 16];
+
+#[no_mangle]
+fn main() -> i32 {
+    let brk = get_brk();
+    assert!(brk > 0);
+    assert_eq!(brk % 4096, 0);
+    assert!(brk as usize > main as usize);
+    assert!(brk as usize > unsafe { DATA.as_ptr() as usize });
+    assert_eq!(sbrk(0), brk);
+    assert_eq!(sbrk(4096), brk);
+    assert_eq!(get_brk(), brk + 4096);
+    println!("Test 04_get_brk OK!");
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{madvise, mmap, munmap, MADV_DONTNEED};
+
+/*
+理想结果：MADV_DONTNEED 之后映射仍然存在，被回收的页面读回全零，未被回收的页面内容不变，输出 Test 04_madvise OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    let prot: usize = 3;
+    assert_eq!(0, mmap(start, len * 2, prot));
+    for i in start..(start + len * 2) {
+        let addr: *mut u8 = i as *mut u8;
+        unsafe {
+            *addr = i as u8;
+        }
+    }
+    // 回收第二页的页帧
+    assert_eq!(madvise(start + len, len, MADV_DONTNEED), 0);
+    for i in start..(start + len) {
+        let addr: *mut u8 = i as *mut u8;
+        unsafe {
+            assert_eq!(*addr, i as u8);
+        }
+    }
+    for i in (start + len)..(start + len * 2) {
+        let addr: *mut u8 = i as *mut u8;
+        unsafe {
+            assert_eq!(*addr, 0);
+        }
+    }
+    // 范围内有未映射的页面
+    assert_eq!(madvise(start, len * 3, MADV_DONTNEED), -1);
+    assert_eq!(munmap(start, len * 2), 0);
+    assert_eq!(madvise(start, len, MADV_DONTNEED), -1);
+    println!("Test 04_madvise OK!");
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mmap_batch, munmap, MmapSpec};
+
+/*
+理想结果：三个合法的区域全部被映射；中间一项不合法时一个区域都不会被映射，输出 Test 04_mmap_batch OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    let specs = [
+        MmapSpec::new(start, len, 3),
+        MmapSpec::new(start + len * 2, len * 2, 3),
+        MmapSpec::new(start + len * 8, len, 3),
+    ];
+    assert_eq!(mmap_batch(&specs), 0);
+    for spec in specs.iter() {
+        for i in (spec.start..(spec.start + spec.len)).step_by(len) {
+            let addr: *mut u8 = i as *mut u8;
+            unsafe {
+                *addr = i as u8;
+                assert_eq!(*addr, i as u8);
+            }
+        }
+    }
+    // 中间一项与已有的映射重叠
+    let other: usize = 0x20000000;
+    let specs = [
+        MmapSpec::new(other, len, 3),
+        MmapSpec::new(start, len, 3),
+        MmapSpec::new(other + len, len, 3),
+    ];
+    assert_eq!(mmap_batch(&specs), -1);
+    // 第一项和第三项都没有被映射，可以再次单独映射
+    assert_eq!(mmap(other, len * 2, 3), 0);
+    assert_eq!(munmap(other, len * 2), 0);
+    println!("Test 04_mmap_batch OK!");
+    0
+}
@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mmap_hint, munmap};
+
+/*
+理想结果：建议的地址空闲时直接使用，与已有映射重叠时映射到其上第一段足够大的空闲区域，输出 Test 04_mmap_hint OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    let prot: usize = 3;
+    assert_eq!(mmap_hint(start, len, prot), start as isize);
+    assert_eq!(mmap(start + len * 2, len, prot), 0);
+    // 两页的区域放不进 [start + len, start + len * 2) 的空隙
+    let addr = mmap_hint(start, len * 2, prot) as usize;
+    assert_eq!(addr, start + len * 3);
+    for i in (addr..(addr + len * 2)).step_by(len) {
+        let addr: *mut u8 = i as *mut u8;
+        unsafe {
+            *addr = i as u8;
+            assert_eq!(*addr, i as u8);
+        }
+    }
+    // 一页的区域恰好放进空隙
+    assert_eq!(mmap_hint(start, len, prot), (start + len) as isize);
+    // 非法的参数返回负的错误码
+    assert_eq!(mmap_hint(start + 1, len, prot), -22);
+    assert_eq!(mmap_hint(start, len, 0), -22);
+    // Trap 上下文所在的页面不能映射
+    assert_eq!(mmap_hint(usize::MAX - len * 2 + 1, len, prot), -1);
+    assert_eq!(munmap(start, len * 5), 0);
+    println!("Test 04_mmap_hint OK!");
+    0
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mprotect, PROT_NONE};
+
+/*
+理想结果：读取被 mprotect 设为不允许访问的页面时触发访存异常，被杀死。不输出 error 就算过。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    let prot: usize = 3;
+    assert_eq!(0, mmap(start, len * 2, prot));
+    assert_eq!(mprotect(start + len, len, PROT_NONE), 0);
+    // 其余页面仍然可以正常访问
+    let addr: *mut u8 = start as *mut u8;
+    unsafe {
+        *addr = start as u8;
+        assert_eq!(*addr, start as u8);
+    }
+    let addr: *const u8 = (start + len) as *const u8;
+    unsafe {
+        let _ = core::ptr::read_volatile(addr);
+    }
+    println!("Should cause error, Test 04_mprotect0 fail!");
+    0
+}
@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mprotect, PROT_NONE};
+
+/*
+理想结果：写入被 mprotect 设为不允许访问的页面时触发访存异常，被杀死。不输出 error 就算过。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    let prot: usize = 3;
+    assert_eq!(0, mmap(start, len, prot));
+    assert_eq!(mprotect(start, len, PROT_NONE), 0);
+    // 页面仍然被占用，不能再次映射
+    assert_eq!(mmap(start, len, prot), -1);
+    let addr: *mut u8 = start as *mut u8;
+    unsafe {
+        *addr = start as u8;
+    }
+    println!("Should cause error, Test 04_mprotect1 fail!");
+    0
+}
@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::query_page;
+
+/*
+理想结果：代码页为 R|X，数据页为 R|W，未映射以及只属于内核的页面返回 -1，输出 Test 04_query_page OK!
+*/
+
+const V: isize = 1 << 0;
+const R: isize = 1 << 1;
+const W: isize = 1 << 2;
+const X: isize = 1 << 3;
+const U: isize = 1 << 4;
+
+static mut DATA: [u8; 16] = [1; 16];
+
+#[no_mangle]
+fn main() -> i32 {
+    let code = main as usize;
+    let data = unsafe { DATA.as_ptr() as usize };
+    assert_eq!(query_page(code), V | R | X | U);
+    assert_eq!(query_page(data), V | R | W | U);
+    assert_eq!(query_page(0x10000000), -1);
+    // 跳板和 Trap 上下文只属于内核，同样返回 -1
+    assert_eq!(query_page(usize::MAX), -1);
+    assert_eq!(query_page(usize::MAX - 0x1fff), -1);
+    println!("Test 04_query_page OK!");
+    0
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::sbrk;
+
+/*
+理想结果：扩展后的堆可以正常读写，缩小后访问被移出的页面触发访存异常，程序被杀死。不输出 error 就算过。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let len: isize = 4096;
+    let heap_bottom = sbrk(0);
+    assert!(heap_bottom > 0);
+    // 扩展两页，返回扩展之前的 program break
+    assert_eq!(sbrk(len * 2), heap_bottom);
+    assert_eq!(sbrk(0), heap_bottom + len * 2);
+    for i in heap_bottom..(heap_bottom + len * 2) {
+        let addr: *mut u8 = i as *mut u8;
+        unsafe {
+            *addr = i as u8;
+        }
+    }
+    for i in heap_bottom..(heap_bottom + len * 2) {
+        let addr: *mut u8 = i as *mut u8;
+        unsafe {
+            assert_eq!(*addr, i as u8);
+        }
+    }
+    // 不能缩小到堆底之下
+    assert_eq!(sbrk(-len * 3), -1);
+    assert_eq!(sbrk(0), heap_bottom + len * 2);
+    // 缩小一页后，被移出的页面不能再访问
+    assert_eq!(sbrk(-len), heap_bottom + len * 2);
+    let addr: *mut u8 = (heap_bottom + len) as *mut u8;
+    unsafe {
+        *addr = 0;
+    }
+    println!("Should cause error, Test 04_sbrk fail!");
+    0
+}
@@ -18,6 +18,7 @@ fn main() -> i32 {
     assert_eq!(0, mmap(start, len, prot));
     assert_eq!(munmap(start, len + 1), -1);
     assert_eq!(munmap(start + 1, len - 1), -1);
+    assert_eq!(munmap(start, 0), -1);
     println!("Test 04_6 ummap2 OK!");
     0
 }
@@ -98,6 +98,21 @@ impl TimeVal {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+pub const CLOCK_MONOTONIC: usize = 1;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TaskStatus {
     UnInit,
@@ -119,6 +134,9 @@ pub struct TaskInfo {
     pub status: TaskStatus,
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
     pub time: usize,
+    pub peak_frames: usize,
+    pub resident_frames: usize,
+    pub page_faults: usize,
 }
 
 impl TaskInfo {
@@ -127,6 +145,9 @@ impl TaskInfo {
             status: TaskStatus::UnInit,
             syscall_times: [0; MAX_SYSCALL_NUM],
             time: 0,
+            peak_frames: 0,
+            resident_frames: 0,
+            page_faults: 0,
         }
     }
 }
@@ -232,6 +253,10 @@ pub fn get_time() -> isize {
     }
 }
 
+pub fn clock_gettime(clk_id: usize, ts: &mut TimeSpec) -> isize {
+    sys_clock_gettime(clk_id, ts)
+}
+
 pub fn getpid() -> isize {
     sys_getpid()
 }
@@ -292,6 +317,56 @@ pub fn munmap(start: usize, len: usize) -> isize {
     sys_munmap(start, len)
 }
 
+/// mmap_batch 中的一项，含义与 mmap 的三个参数相同
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct MmapSpec {
+    pub start: usize,
+    pub len: usize,
+    pub prot: usize,
+}
+
+impl MmapSpec {
+    pub fn new(start: usize, len: usize, prot: usize) -> Self {
+        Self { start, len, prot }
+    }
+}
+
+/// 一次映射多个区域，任意一个失败时全部撤销并返回 -1
+pub fn mmap_batch(specs: &[MmapSpec]) -> isize {
+    sys_mmap_batch(specs)
+}
+
+/// start 只是建议的地址，与已有映射重叠时向上寻找空闲区域，成功时返回实际映射的起始地址
+pub fn mmap_hint(start: usize, len: usize, prot: usize) -> isize {
+    sys_mmap_hint(start, len, prot)
+}
+
+/// mprotect 的 prot 参数，表示不允许任何访问
+pub const PROT_NONE: usize = 0;
+
+pub fn mprotect(start: usize, len: usize, prot: usize) -> isize {
+    sys_mprotect(start, len, prot)
+}
+
+pub const MADV_DONTNEED: usize = 4;
+
+pub fn madvise(start: usize, len: usize, advice: usize) -> isize {
+    sys_madvise(start, len, advice)
+}
+
+pub fn sbrk(increment: isize) -> isize {
+    sys_sbrk(increment)
+}
+
+pub fn query_page(addr: usize) -> isize {
+    sys_query_page(addr)
+}
+
+pub fn get_brk() -> isize {
+    sys_get_brk()
+}
+
 pub fn spawn(path: &str) -> isize {
     sys_spawn(path)
 }
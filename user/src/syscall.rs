@@ -1,6 +1,6 @@
 use crate::TaskInfo;
 
-use super::{Stat, TimeVal};
+use super::{MmapSpec, Stat, TimeSpec, TimeVal};
 
 pub const SYSCALL_OPENAT: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
@@ -11,6 +11,7 @@ pub const SYSCALL_LINKAT: usize = 37;
 pub const SYSCALL_FSTAT: usize = 80;
 pub const SYSCALL_EXIT: usize = 93;
 pub const SYSCALL_SLEEP: usize = 101;
+pub const SYSCALL_CLOCK_GETTIME: usize = 113;
 pub const SYSCALL_YIELD: usize = 124;
 pub const SYSCALL_GETTIMEOFDAY: usize = 169;
 pub const SYSCALL_GETPID: usize = 172;
@@ -21,12 +22,19 @@ pub const SYSCALL_WAITPID: usize = 260;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
+pub const SYSCALL_MPROTECT: usize = 226;
+pub const SYSCALL_MADVISE: usize = 233;
 pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MAIL_READ: usize = 401;
 pub const SYSCALL_MAIL_WRITE: usize = 402;
 pub const SYSCALL_DUP: usize = 24;
 pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_SBRK: usize = 414;
+pub const SYSCALL_QUERY_PAGE: usize = 415;
+pub const SYSCALL_GET_BRK: usize = 416;
+pub const SYSCALL_MMAP_BATCH: usize = 417;
+pub const SYSCALL_MMAP_HINT: usize = 418;
 pub const SYSCALL_THREAD_CREATE: usize = 460;
 pub const SYSCALL_WAITTID: usize = 462;
 pub const SYSCALL_MUTEX_CREATE: usize = 463;
@@ -154,6 +162,10 @@ pub fn sys_yield() -> isize {
     syscall(SYSCALL_YIELD, [0, 0, 0])
 }
 
+pub fn sys_clock_gettime(clk_id: usize, ts: &mut TimeSpec) -> isize {
+    syscall(SYSCALL_CLOCK_GETTIME, [clk_id, ts as *mut _ as usize, 0])
+}
+
 pub fn sys_get_time(time: &TimeVal, tz: usize) -> isize {
     syscall(SYSCALL_GETTIMEOFDAY, [time as *const _ as usize, tz, 0])
 }
@@ -189,6 +201,34 @@ pub fn sys_munmap(start: usize, len: usize) -> isize {
     syscall(SYSCALL_MUNMAP, [start, len, 0])
 }
 
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [start, len, prot])
+}
+
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    syscall(SYSCALL_MADVISE, [start, len, advice])
+}
+
+pub fn sys_sbrk(increment: isize) -> isize {
+    syscall(SYSCALL_SBRK, [increment as usize, 0, 0])
+}
+
+pub fn sys_query_page(addr: usize) -> isize {
+    syscall(SYSCALL_QUERY_PAGE, [addr, 0, 0])
+}
+
+pub fn sys_get_brk() -> isize {
+    syscall(SYSCALL_GET_BRK, [0, 0, 0])
+}
+
+pub fn sys_mmap_batch(specs: &[MmapSpec]) -> isize {
+    syscall(SYSCALL_MMAP_BATCH, [specs.as_ptr() as usize, specs.len(), 0])
+}
+
+pub fn sys_mmap_hint(start: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MMAP_HINT, [start, len, prot])
+}
+
 pub fn sys_spawn(path: &str) -> isize {
     syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
 }